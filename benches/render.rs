@@ -0,0 +1,144 @@
+// Compares drawing a large pattern glyph by glyph with `put_pixel` (how the
+// renderer used to draw) against drawing it through the image's `Canvas`, and
+// times rendering it into a new image.  Run with `cargo bench`.
+
+extern crate image;
+extern crate patren;
+
+use patren::canvas::Canvas;
+use patren::{pattern_font, raster, renderer, renoise};
+
+const TRACKS: usize = 40;
+const LINES: usize = 512;
+const ITERATIONS: u32 = 10;
+
+const NOTES: [&str; 12] = ["C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-"];
+
+// An image drawn to the way the renderer drew before drawing straight into
+// the pixel buffer: glyph by glyph, and pixel by pixel with `put_pixel`.
+struct PerPixel(image::ImageBuffer<image::Rgba<u8>, Vec<u8>>);
+
+impl Canvas for PerPixel {
+    fn width(&self) -> u32 {
+        self.0.width()
+    }
+
+    fn height(&self) -> u32 {
+        self.0.height()
+    }
+
+    fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: &image::Rgba<u8>) {
+        for real_y in y..(y + height).min(self.0.height()) {
+            for real_x in x..(x + width).min(self.0.width()) {
+                self.0.put_pixel(real_x, real_y, *color);
+            }
+        }
+    }
+
+    fn fade_rect(&mut self, x: u32, y: u32, width: u32, height: u32, alpha: u8) {
+        for real_y in y..(y + height).min(self.0.height()) {
+            for real_x in x..(x + width).min(self.0.width()) {
+                let pixel = self.0.get_pixel_mut(real_x, real_y);
+                pixel[3] = pixel[3].min(alpha);
+            }
+        }
+    }
+
+    fn draw_glyph(&mut self, code: u8, x: u32, y: u32, color: &image::Rgba<u8>) {
+        raster::draw_glyph_per_pixel(&mut self.0, pattern_font::char(code), x, y, color);
+    }
+}
+
+fn main() {
+    let song = renoise::Song::from_xml(song_xml()).unwrap();
+    let tracks: Vec<usize> = (0..TRACKS).collect();
+    let (width, height) = renderer::region_size(&song, 0, &tracks, 0..LINES as u32, renderer::NoteColors::Default);
+    println!("pattern of {} lines and {} tracks, {}x{} pixels", LINES, TRACKS, width, height);
+
+    // the pattern is drawn into the same image every time so that allocating
+    // it (and the page faults of touching it for the first time) does not
+    // skew the times
+    let mut per_pixel_image = PerPixel(image::ImageBuffer::new(width, height));
+    let per_pixel = time("pattern, per pixel", || {
+        renderer::draw_region(&mut per_pixel_image, &song, 0, &tracks, 0..LINES as u32, renderer::NoteColors::Default);
+    });
+
+    let mut imgbuf: image::ImageBuffer<image::Rgba<u8>, Vec<u8>> = image::ImageBuffer::new(width, height);
+    let blitted = time("pattern, canvas", || {
+        renderer::draw_region(&mut imgbuf, &song, 0, &tracks, 0..LINES as u32, renderer::NoteColors::Default);
+    });
+    println!("speedup: {:.1}x", per_pixel / blitted);
+
+    // most of the time of rendering a pattern this large goes into touching
+    // the freshly allocated image for the first time
+    let color = image::Rgba([0xff, 0xff, 0xff, 0xff]);
+    time("allocating the image", || {
+        let mut imgbuf: image::ImageBuffer<image::Rgba<u8>, Vec<u8>> = image::ImageBuffer::new(width, height);
+        raster::fill_rect(&mut imgbuf, 0, 0, width, height, &color);
+    });
+    time("whole pattern", || {
+        renderer::render(&song, 0, renderer::NoteColors::Default);
+    });
+}
+
+// Runs `f` a few times and prints and returns the best time in
+// milliseconds, which is the least disturbed by whatever else is running.
+fn time<F: FnMut()>(name: &str, mut f: F) -> f64 {
+    let milliseconds = (0..ITERATIONS).map(|_| {
+        let start = std::time::Instant::now();
+        f();
+        start.elapsed().as_secs_f64() * 1000.0
+    }).fold(f64::INFINITY, f64::min);
+    println!("{:<24} {:8.2} ms", name, milliseconds);
+
+    milliseconds
+}
+
+// Builds a song with a single, densely filled pattern.  The contents come
+// from a simple linear congruential generator, so every run is the same.
+fn song_xml() -> String {
+    let mut seed: u32 = 1;
+    let mut random = move |n: u32| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (seed >> 16) % n
+    };
+
+    let mut tracks = String::new();
+    let mut pattern_tracks = String::new();
+    for track in 0..TRACKS {
+        let track_type = if track == TRACKS - 1 { "SequencerMasterTrack" } else { "SequencerTrack" };
+        let pattern_track_type = if track == TRACKS - 1 { "PatternMasterTrack" } else { "PatternTrack" };
+        tracks += &format!(
+            "<{0} type=\"{0}\"><Name>Track {1}</Name><Color>{2},{3},{4}</Color><State>Active</State>\
+             <NumberOfVisibleNoteColumns>2</NumberOfVisibleNoteColumns><NumberOfVisibleEffectColumns>1</NumberOfVisibleEffectColumns>\
+             <VolumeColumnIsVisible>true</VolumeColumnIsVisible><PanningColumnIsVisible>false</PanningColumnIsVisible>\
+             <DelayColumnIsVisible>false</DelayColumnIsVisible></{0}>",
+            track_type, track + 1, random(256), random(256), random(256)
+        );
+
+        pattern_tracks += &format!("<{0} type=\"{0}\"><AliasPatternIndex>-1</AliasPatternIndex><Lines>", pattern_track_type);
+        for line in 0..LINES {
+            if random(3) != 0 {
+                continue;
+            }
+            let note = format!("{}{}", NOTES[random(12) as usize], 2 + random(5));
+            pattern_tracks += &format!(
+                "<Line index=\"{}\"><NoteColumns><NoteColumn><Note>{}</Note><Instrument>{:02X}</Instrument><Volume>{:02X}</Volume></NoteColumn></NoteColumns>\
+                 <EffectColumns><EffectColumn><Value>{:02X}</Value><Number>0A</Number></EffectColumn></EffectColumns></Line>",
+                line, note, random(16), random(128), random(256)
+            );
+        }
+        pattern_tracks += &format!("</Lines></{}>", pattern_track_type);
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <RenoiseSong doc_version=\"63\">\
+         <GlobalSongData><BeatsPerMin>125</BeatsPerMin><LinesPerBeat>4</LinesPerBeat><TicksPerLine>12</TicksPerLine><SongName>Benchmark</SongName><Artist>patren</Artist></GlobalSongData>\
+         <Tracks>{}</Tracks>\
+         <PatternPool><Patterns><Pattern><NumberOfLines>{}</NumberOfLines><Tracks>{}</Tracks></Pattern></Patterns></PatternPool>\
+         <PatternSequence><SequenceEntries><SequenceEntry><IsSectionStart>true</IsSectionStart><SectionName>Benchmark</SectionName><Pattern>0</Pattern><MutedTracks></MutedTracks></SequenceEntry></SequenceEntries></PatternSequence>\
+         </RenoiseSong>",
+        tracks, LINES, pattern_tracks
+    )
+}
@@ -0,0 +1,260 @@
+extern crate gif;
+extern crate image;
+
+use std::collections::HashMap;
+
+use super::renderer::{HEADER_HEIGHT, LINE_HEIGHT};
+use super::theme::*;
+
+const VIEW_LINES: u32 = 33; // number of lines visible around the playhead
+
+// Browsers clamp GIF frame delays under 2cs (20ms) to 10cs, which would slow
+// the animation down, so frames are kept at 2cs or longer.
+const MIN_FRAME_DURATION: f64 = 20.0;
+const MIN_FRAME_DELAY: u32 = 2; // the same in the 10ms units of GIF delays
+
+// Renders an animated GIF of a pattern scrolling past a playhead row in the
+// centre of the image, at the speed the song is played at.
+pub fn render_gif<W: std::io::Write>(song: &super::renoise::Song, pattern: usize, w: W) -> Result<(), Box<dyn std::error::Error>> {
+    let song_data = &song.global_song_data;
+    let pat = &song.pattern_pool.patterns[pattern];
+    let strip = super::renderer::render(song, pattern, super::renderer::NoteColors::Default);
+
+    let line_duration = line_duration(song_data.beats_per_min, song_data.lines_per_beat);
+    let steps = steps_per_line(line_duration, song_data.ticks_per_line);
+
+    let width: u32 = strip.width();
+    let height: u32 = HEADER_HEIGHT + VIEW_LINES * LINE_HEIGHT;
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        bail!("GIF images can be at most 65535 pixels wide and high, not {}x{}", width, height);
+    }
+
+    let mut encoder = gif::Encoder::new(w, width as u16, height as u16, &[])?;
+    encoder.write_extension(gif::ExtensionData::Repetitions(gif::Repeat::Infinite))?;
+
+    for (step, delay) in gif_frame_delays(pat.number_of_lines * steps, line_duration / steps as f64) {
+        let line: u32 = step / steps;
+        let scroll: u32 = line * LINE_HEIGHT + (step % steps) * LINE_HEIGHT / steps;
+        let view = render_view(&strip, scroll, height, line.is_multiple_of(song_data.lines_per_beat.max(1)));
+        encoder.write_frame(&gif_frame(&view, delay as u16))?;
+    }
+
+    Ok(())
+}
+
+// Splits `number_of_steps` scroll steps of `step_duration` milliseconds into
+// GIF frames, returning the first step and the delay of each frame.  The
+// delays are rounded to 10ms, so the error is carried on to not drift away
+// from the actual tempo, and steps too short for a frame of their own are
+// merged into one.
+fn gif_frame_delays(number_of_steps: u32, step_duration: f64) -> Vec<(u32, u32)> {
+    let mut frames: Vec<(u32, u32)> = vec![];
+    let mut elapsed: u32 = 0;
+    let mut first_step: u32 = 0;
+
+    for step in 0..number_of_steps {
+        let end = ((step + 1) as f64 * step_duration / 10.0).round() as u32;
+        if end - elapsed >= MIN_FRAME_DELAY {
+            frames.push((first_step, end - elapsed));
+            elapsed = end;
+            first_step = step + 1;
+        }
+    }
+
+    // what is left over is too short for a frame, and is added to the last
+    let end = (number_of_steps as f64 * step_duration / 10.0).round() as u32;
+    match frames.last_mut() {
+        Some((_, delay)) => *delay += end - elapsed,
+        None if number_of_steps > 0 => frames.push((0, end.max(MIN_FRAME_DELAY))),
+        None => {}
+    }
+
+    frames
+}
+
+// A line of the song as it is played back, with its start time and duration
+// in milliseconds, and the lines per beat and ticks per line in effect.
+pub struct PlayedLine {
+    pub sequence_index: usize,
+    pub line: u32,
+    pub start: f64,
+    pub duration: f64,
+    pub lines_per_beat: u32,
+    pub ticks_per_line: u32
+}
+
+// Lays out the lines of the song in the order of the pattern sequence,
+// following the tempo changes of ZT (beats per minute), ZL (lines per beat)
+// and ZK (ticks per line) commands.  Lines skipped by pattern breaks are left
+// out, and lines with a delay last as many lines longer.
+pub fn timeline(song: &super::renoise::Song) -> Vec<PlayedLine> {
+    let song_data = &song.global_song_data;
+    let flows = super::flow::sequence_flow(song);
+    let (mut beats_per_min, mut lines_per_beat, mut ticks_per_line) = (song_data.beats_per_min, song_data.lines_per_beat, song_data.ticks_per_line);
+    let mut played_lines: Vec<PlayedLine> = vec![];
+    let mut time: f64 = 0.0;
+
+    for (sequence_index, entry) in song.pattern_sequence.sequence_entries.iter().enumerate() {
+        let pattern = entry.pattern as usize;
+        let pat = &song.pattern_pool.patterns[pattern];
+
+        // collect the tempo commands of all tracks by line
+        let mut commands: Vec<Vec<&super::renoise::EffectColumn>> = (0..pat.number_of_lines).map(|_| vec![]).collect();
+        for track_index in 0..pat.tracks.len() {
+            for line in song.pattern_track_lines(pattern, track_index).iter().filter(|l| l.index < pat.number_of_lines) {
+                commands[line.index as usize].extend(line.effect_columns.iter().flatten());
+            }
+        }
+
+        let flow = &flows[sequence_index];
+        for (line, effects) in commands.iter().enumerate().filter(|&(line, _)| flow.is_played(line as u32)) {
+            for effect in effects.iter() {
+                let value = match u32::from_str_radix(&effect.value, 16) {
+                    Ok(value) if value > 0 => value,
+                    _ => { continue; }
+                };
+                match effect.number.as_str() {
+                    "ZT" => beats_per_min = value,
+                    "ZL" => lines_per_beat = value,
+                    "ZK" => ticks_per_line = value,
+                    _ => {}
+                }
+            }
+
+            let delay: u32 = flow.delays.iter().filter(|&&(delay_line, _)| delay_line == line as u32).map(|&(_, lines)| lines).sum();
+            let duration = line_duration(beats_per_min, lines_per_beat) * (1 + delay) as f64;
+            played_lines.push(PlayedLine { sequence_index, line: line as u32, start: time, duration, lines_per_beat, ticks_per_line });
+            time += duration;
+        }
+    }
+
+    played_lines
+}
+
+// Renders the song as a sequence of frames at a fixed frame rate, each showing
+// the pattern currently played scrolling past the playhead row, and passes
+// them to `write_frame` along with their number.
+pub fn render_frames<F>(song: &super::renoise::Song, frames_per_second: u32, mut write_frame: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: FnMut(usize, &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> Result<(), Box<dyn std::error::Error>>
+{
+    let played_lines = timeline(song);
+    let duration: f64 = played_lines.last().map_or(0.0, |l| l.start + l.duration);
+    let number_of_frames = (duration * frames_per_second as f64 / 1000.0).ceil() as usize;
+    let height: u32 = HEADER_HEIGHT + VIEW_LINES * LINE_HEIGHT;
+
+    let mut strip_index: Option<usize> = None;
+    let mut strip = image::ImageBuffer::new(0, 0);
+    for frame_index in 0..number_of_frames {
+        let time: f64 = frame_index as f64 * 1000.0 / frames_per_second as f64;
+        let played_line = &played_lines[played_lines.partition_point(|l| l.start <= time).max(1) - 1];
+
+        if strip_index != Some(played_line.sequence_index) {
+            strip = super::renderer::render_sequence_entry(song, played_line.sequence_index, super::renderer::MuteStyle::Dim, super::renderer::NoteColors::Default, None);
+            strip_index = Some(played_line.sequence_index);
+        }
+
+        // scroll on tick by tick, like the GIF animations do
+        let ticks_per_line = played_line.ticks_per_line.max(1);
+        let tick: u32 = (((time - played_line.start) / played_line.duration * ticks_per_line as f64) as u32).min(ticks_per_line - 1);
+        let scroll: u32 = played_line.line * LINE_HEIGHT + tick * LINE_HEIGHT / ticks_per_line;
+        // the strip shades the beats by the lines per beat of the song, which
+        // the playhead follows even after ZL commands, so that they agree
+        let highlighted: bool = played_line.line.is_multiple_of(song.global_song_data.lines_per_beat.max(1));
+
+        let view = render_view(&strip, scroll, height, highlighted);
+        write_frame(frame_index, &view)?;
+    }
+
+    Ok(())
+}
+
+// Returns the duration of a line in milliseconds.
+pub fn line_duration(beats_per_min: u32, lines_per_beat: u32) -> f64 {
+    60_000.0 / (beats_per_min.max(1) * lines_per_beat.max(1)) as f64
+}
+
+// Returns into how many frames a line is split for smooth scrolling: the
+// largest divisor of the ticks per line whose frames are not too short.
+fn steps_per_line(line_duration: f64, ticks_per_line: u32) -> u32 {
+    (1..=ticks_per_line.max(1)).rev()
+        .filter(|&steps| ticks_per_line.max(1).is_multiple_of(steps))
+        .find(|&steps| line_duration / steps as f64 >= MIN_FRAME_DURATION)
+        .unwrap_or(1)
+}
+
+// Renders a window of `strip` (an image as returned by `renderer::render`)
+// with the line at `scroll` pixels below the track header placed on the
+// playhead row.  The track header stays in place.
+pub fn render_view(strip: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, scroll: u32, height: u32, highlighted: bool) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    let mut view = image::ImageBuffer::from_pixel(strip.width(), height, COLOR_BACK.normal);
+    let playhead_y: u32 = HEADER_HEIGHT + (height - HEADER_HEIGHT) / LINE_HEIGHT / 2 * LINE_HEIGHT;
+
+    for y in playhead_y..(playhead_y + LINE_HEIGHT).min(height) {
+        for x in 0..view.width() {
+            view.put_pixel(x, y, COLOR_PLAYHEAD.get(highlighted));
+        }
+    }
+
+    for y in 0..height {
+        let source_y: i64 = if y < HEADER_HEIGHT {
+            y as i64
+        } else {
+            y as i64 - playhead_y as i64 + (HEADER_HEIGHT + scroll) as i64
+        };
+        if source_y < 0 || source_y >= strip.height() as i64 || (y >= HEADER_HEIGHT && source_y < HEADER_HEIGHT as i64) {
+            continue;
+        }
+
+        for x in 0..view.width() {
+            let pixel = strip.get_pixel(x, source_y as u32);
+            blend(view.get_pixel_mut(x, y), pixel);
+        }
+    }
+
+    view
+}
+
+fn blend(background: &mut image::Rgba<u8>, foreground: &image::Rgba<u8>) {
+    let alpha = foreground[3] as u32;
+    for i in 0..3 {
+        background[i] = ((foreground[i] as u32 * alpha + background[i] as u32 * (255 - alpha)) / 255) as u8;
+    }
+}
+
+// Converts an image to a GIF frame.  The rendered images usually have only a
+// few colours, in which case they are used as the palette as they are;
+// otherwise the colours get quantized.
+fn gif_frame(view: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, delay: u16) -> gif::Frame<'static> {
+    let mut palette: Vec<u8> = vec![];
+    let mut indices: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut pixels: Vec<u8> = Vec::with_capacity((view.width() * view.height()) as usize);
+
+    for pixel in view.pixels() {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let index = match indices.get(&color) {
+            Some(&index) => index,
+            None if indices.len() < 256 => {
+                let index = indices.len() as u8;
+                indices.insert(color, index);
+                palette.extend_from_slice(&color);
+                index
+            },
+            None => {
+                pixels.clear();
+                break;
+            }
+        };
+        pixels.push(index);
+    }
+
+    let mut frame = if pixels.len() == (view.width() * view.height()) as usize {
+        gif::Frame::from_palette_pixels(view.width() as u16, view.height() as u16, &pixels, &palette, None)
+    } else {
+        let rgb: Vec<u8> = view.pixels().flat_map(|p| vec![p[0], p[1], p[2]]).collect();
+        gif::Frame::from_rgb_speed(view.width() as u16, view.height() as u16, &rgb, 10)
+    };
+    frame.delay = delay;
+
+    frame
+}
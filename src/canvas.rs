@@ -0,0 +1,152 @@
+extern crate image;
+
+use super::raster;
+
+// Something the pattern renderer can draw to.  Coordinates are in pixels
+// from the top left corner, and anything outside of the canvas is clipped.
+// `image::ImageBuffer` is the canvas the images are rendered to; other
+// targets, such as the framebuffer of a display, only need to implement this
+// trait to get patterns drawn on them.
+pub trait Canvas {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+
+    fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: &image::Rgba<u8>);
+
+    // Lowers the opacity of what has been drawn into a rectangle to at most
+    // `alpha`, used for muted tracks.
+    fn fade_rect(&mut self, x: u32, y: u32, width: u32, height: u32, alpha: u8);
+
+    // Draws a glyph of the pattern font, given as its code page 437 code.
+    // Only the pixels set in the glyph are drawn.
+    fn draw_glyph(&mut self, code: u8, x: u32, y: u32, color: &image::Rgba<u8>);
+
+    fn draw_text(&mut self, codes: &[u8], x: u32, y: u32, color: &image::Rgba<u8>) {
+        for (index, &code) in codes.iter().enumerate() {
+            self.draw_glyph(code, x + index as u32 * super::pattern_font::CHAR_WIDTH as u32, y, color);
+        }
+    }
+
+    // Draws the same text at each of `positions`, such as the dots of the
+    // empty cells of a column.  Canvases that can rasterise the text once and
+    // copy it around should do so.
+    fn stamp_text(&mut self, codes: &[u8], positions: &[(u32, u32)], color: &image::Rgba<u8>) {
+        for &(x, y) in positions.iter() {
+            self.draw_text(codes, x, y, color);
+        }
+    }
+
+    // Draws a line one pixel wide from `x0`, `y0` to `x1`, `y1`, both ends
+    // included.
+    fn draw_line(&mut self, x0: u32, y0: u32, x1: u32, y1: u32, color: &image::Rgba<u8>) {
+        let (dx, dy) = ((x1 as i64 - x0 as i64).abs(), -(y1 as i64 - y0 as i64).abs());
+        let (step_x, step_y) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+        let (mut x, mut y) = (x0 as i64, y0 as i64);
+        let mut error = dx + dy;
+
+        loop {
+            self.fill_rect(x as u32, y as u32, 1, 1, color);
+            if x == x1 as i64 && y == y1 as i64 {
+                break;
+            }
+
+            let doubled_error = 2 * error;
+            if doubled_error >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled_error <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+}
+
+impl Canvas for image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    fn width(&self) -> u32 {
+        self.dimensions().0
+    }
+
+    fn height(&self) -> u32 {
+        self.dimensions().1
+    }
+
+    fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: &image::Rgba<u8>) {
+        raster::fill_rect(self, x, y, width, height, color);
+    }
+
+    fn fade_rect(&mut self, x: u32, y: u32, width: u32, height: u32, alpha: u8) {
+        for real_y in y..(y + height).min(self.dimensions().1) {
+            for real_x in x..(x + width).min(self.dimensions().0) {
+                let pixel = self.get_pixel_mut(real_x, real_y);
+                pixel[3] = pixel[3].min(alpha);
+            }
+        }
+    }
+
+    fn draw_glyph(&mut self, code: u8, x: u32, y: u32, color: &image::Rgba<u8>) {
+        raster::draw_text(self, Some(code), x, y, color);
+    }
+
+    fn draw_text(&mut self, codes: &[u8], x: u32, y: u32, color: &image::Rgba<u8>) {
+        raster::draw_text(self, codes.iter().cloned(), x, y, color);
+    }
+
+    fn stamp_text(&mut self, codes: &[u8], positions: &[(u32, u32)], color: &image::Rgba<u8>) {
+        let stamp = raster::Stamp::text(codes, color);
+        for &(x, y) in positions.iter() {
+            raster::draw_stamp(self, &stamp, x, y);
+        }
+    }
+}
+
+// A canvas drawing into another one, moved right by `x` and down by `y`, so
+// that something can be drawn anywhere on a canvas that draws from its top
+// left corner.
+pub struct Offset<'a, C: Canvas> {
+    canvas: &'a mut C,
+    x: u32,
+    y: u32
+}
+
+impl<'a, C: Canvas> Offset<'a, C> {
+    pub fn new(canvas: &'a mut C, x: u32, y: u32) -> Offset<'a, C> {
+        Offset { canvas, x, y }
+    }
+}
+
+impl<'a, C: Canvas> Canvas for Offset<'a, C> {
+    fn width(&self) -> u32 {
+        self.canvas.width().saturating_sub(self.x)
+    }
+
+    fn height(&self) -> u32 {
+        self.canvas.height().saturating_sub(self.y)
+    }
+
+    fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: &image::Rgba<u8>) {
+        self.canvas.fill_rect(self.x + x, self.y + y, width, height, color);
+    }
+
+    fn fade_rect(&mut self, x: u32, y: u32, width: u32, height: u32, alpha: u8) {
+        self.canvas.fade_rect(self.x + x, self.y + y, width, height, alpha);
+    }
+
+    fn draw_glyph(&mut self, code: u8, x: u32, y: u32, color: &image::Rgba<u8>) {
+        self.canvas.draw_glyph(code, self.x + x, self.y + y, color);
+    }
+
+    fn draw_text(&mut self, codes: &[u8], x: u32, y: u32, color: &image::Rgba<u8>) {
+        self.canvas.draw_text(codes, self.x + x, self.y + y, color);
+    }
+
+    fn stamp_text(&mut self, codes: &[u8], positions: &[(u32, u32)], color: &image::Rgba<u8>) {
+        let positions: Vec<(u32, u32)> = positions.iter().map(|&(x, y)| (self.x + x, self.y + y)).collect();
+        self.canvas.stamp_text(codes, &positions, color);
+    }
+
+    fn draw_line(&mut self, x0: u32, y0: u32, x1: u32, y1: u32, color: &image::Rgba<u8>) {
+        self.canvas.draw_line(self.x + x0, self.y + y0, self.x + x1, self.y + y1, color);
+    }
+}
@@ -0,0 +1,54 @@
+extern crate image;
+
+use super::renderer::{self, NoteColors, LINE_HEIGHT};
+use super::theme::*;
+
+const SPACING: u32 = super::pattern_font::CHAR_WIDTH as u32; // around the tiles
+
+// Renders every pattern of the pool and lays them out in a grid `width`
+// pixels wide, each tile labelled with the pattern number and name below it.
+// The tiles are scaled to fit `columns` of them side by side, or about as
+// many columns as rows if not given.  The labels are not scaled, so that they
+// stay readable.
+pub fn render(song: &super::renoise::Song, width: u32, columns: Option<u32>, note_colors: NoteColors) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    let number_of_patterns = song.pattern_pool.patterns.len() as u32;
+    let columns: u32 = columns.unwrap_or_else(|| (number_of_patterns as f64).sqrt().ceil() as u32).clamp(1, number_of_patterns.max(1));
+    let tile_width: u32 = (width.saturating_sub((columns + 1) * SPACING) / columns).max(1);
+
+    let tiles: Vec<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>> = (0..song.pattern_pool.patterns.len()).map(|pattern| {
+        let imgbuf = renderer::render(song, pattern, note_colors);
+        let tile_height: u32 = ((imgbuf.height() as u64 * tile_width as u64) / imgbuf.width().max(1) as u64).max(1) as u32;
+
+        // sharp pixels when scaling up, averaged ones when scaling down
+        let filter = if tile_width > imgbuf.width() { image::FilterType::Nearest } else { image::FilterType::Triangle };
+        image::imageops::resize(&imgbuf, tile_width, tile_height, filter)
+    }).collect();
+
+    // each row is as high as its highest tile
+    let row_heights: Vec<u32> = tiles.chunks(columns as usize)
+        .map(|row| row.iter().map(|tile| tile.height()).max().unwrap_or(0) + LINE_HEIGHT + SPACING)
+        .collect();
+    let height: u32 = SPACING + row_heights.iter().sum::<u32>();
+
+    let mut imgbuf = image::ImageBuffer::from_pixel(width, height, COLOR_BACK.normal);
+
+    let mut y: u32 = SPACING;
+    for (row, row_height) in row_heights.iter().enumerate() {
+        for column in 0..columns as usize {
+            let pattern = row * columns as usize + column;
+            let tile = match tiles.get(pattern) {
+                Some(tile) => tile,
+                None => { break; }
+            };
+            let x: u32 = SPACING + column as u32 * (tile_width + SPACING);
+            image::imageops::overlay(&mut imgbuf, tile, x, y);
+
+            let name = &song.pattern_pool.patterns[pattern].name;
+            let label = format!("{:02} {}", pattern, name.to_uppercase());
+            renderer::render_label(&mut imgbuf, label.trim_end(), x, y + tile.height() + 2, tile_width, &COLOR_DEFAULT.normal);
+        }
+        y += row_height;
+    }
+
+    imgbuf
+}
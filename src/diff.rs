@@ -0,0 +1,330 @@
+extern crate image;
+
+use super::layout::{ColumnKind, Layout};
+use super::raster;
+use super::renderer::{HEADER_HEIGHT, LINE_HEIGHT};
+use super::theme::*;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Change {
+    Added,
+    Removed,
+    Modified
+}
+
+// A cell of a pattern that differs between two versions of a song.  `track`
+// is the index of the track in the new version, and `old_text` what the cell
+// showed in the old one.
+pub struct CellChange {
+    pub track: usize,
+    pub kind: ColumnKind,
+    pub column: usize,
+    pub line: u32,
+    pub change: Change,
+    pub old_text: Option<String>
+}
+
+// Pairs the tracks of the new version of a song with those of the old one:
+// tracks with the same name first, then the remaining tracks at the same
+// position, which are taken to be renamed.  Returns the index of the old
+// track for each new track, or None for tracks that were added.
+pub fn match_tracks(old: &super::renoise::Song, new: &super::renoise::Song) -> Vec<Option<usize>> {
+    let mut matched: Vec<Option<usize>> = vec![None; new.tracks.len()];
+    let mut used: Vec<bool> = vec![false; old.tracks.len()];
+
+    for (new_index, track_info) in new.tracks.iter().enumerate() {
+        let same_name = (0..old.tracks.len()).find(|&old_index| !used[old_index] && old.tracks[old_index].name == track_info.name);
+        if let Some(old_index) = same_name {
+            matched[new_index] = Some(old_index);
+            used[old_index] = true;
+        }
+    }
+
+    for (new_index, old_track) in matched.iter_mut().enumerate() {
+        if old_track.is_none() && new_index < used.len() && !used[new_index] {
+            *old_track = Some(new_index);
+            used[new_index] = true;
+        }
+    }
+
+    matched
+}
+
+// Pairs the patterns of the new version of a song with those of the old one,
+// so that adding or deleting a pattern does not make all patterns after it
+// look changed: patterns with the same lines first, then patterns with the
+// same name, then the remaining patterns at the same position.  Returns the
+// index of the old pattern for each new pattern, or None for patterns that
+// were added.
+pub fn match_patterns(old: &super::renoise::Song, new: &super::renoise::Song, tracks: &[Option<usize>]) -> Vec<Option<usize>> {
+    let (old_patterns, new_patterns) = (&old.pattern_pool.patterns, &new.pattern_pool.patterns);
+    let mut matched: Vec<Option<usize>> = vec![None; new_patterns.len()];
+    let mut used: Vec<bool> = vec![false; old_patterns.len()];
+
+    let same_lines = |old_index: usize, new_index: usize| {
+        old_patterns[old_index].number_of_lines == new_patterns[new_index].number_of_lines
+            && tracks.iter().enumerate().all(|(track, &old_track)| {
+                let new_lines = new.pattern_track_lines(new_index, track);
+                match old_track {
+                    Some(old_track) => old.pattern_track_lines(old_index, old_track) == new_lines,
+                    None => new_lines.is_empty()
+                }
+            })
+    };
+    let same_name = |old_index: usize, new_index: usize| {
+        !new_patterns[new_index].name.is_empty() && old_patterns[old_index].name == new_patterns[new_index].name
+    };
+    let same_position = |old_index: usize, new_index: usize| old_index == new_index;
+
+    let rules: [&dyn Fn(usize, usize) -> bool; 3] = [&same_lines, &same_name, &same_position];
+    for rule in rules.iter() {
+        for (new_index, old_pattern) in matched.iter_mut().enumerate() {
+            if old_pattern.is_some() {
+                continue;
+            }
+            // the pattern at the same position goes first, e.g. of several
+            // empty patterns
+            let found = std::iter::once(new_index).chain(0..used.len()).find(|&old_index| old_index < used.len() && !used[old_index] && rule(old_index, new_index));
+            if let Some(old_index) = found {
+                *old_pattern = Some(old_index);
+                used[old_index] = true;
+            }
+        }
+    }
+
+    matched
+}
+
+// Lists the changes to the structure of a song: added, removed and renamed
+// tracks, added, removed and moved patterns, patterns that changed length
+// with the cells cut off by shortening them, and changes to the pattern
+// sequence.
+pub fn summary(old: &super::renoise::Song, new: &super::renoise::Song) -> Vec<String> {
+    let mut changes: Vec<String> = vec![];
+    let tracks = match_tracks(old, new);
+    let patterns = match_patterns(old, new, &tracks);
+
+    for (new_index, old_track) in tracks.iter().enumerate() {
+        let name = &new.tracks[new_index].name;
+        match *old_track {
+            Some(old_index) if old.tracks[old_index].name != *name => {
+                changes.push(format!("track {} renamed from {} to {}", new_index, old.tracks[old_index].name, name));
+            },
+            Some(_) => {},
+            None => changes.push(format!("track {} ({}) added", new_index, name))
+        }
+    }
+    for (old_index, track_info) in old.tracks.iter().enumerate() {
+        if !tracks.contains(&Some(old_index)) {
+            changes.push(format!("track {} ({}) removed", old_index, track_info.name));
+        }
+    }
+
+    let (old_patterns, new_patterns) = (&old.pattern_pool.patterns, &new.pattern_pool.patterns);
+    for (index, pattern) in new_patterns.iter().enumerate() {
+        let old_index = match patterns[index] {
+            Some(old_index) => old_index,
+            None => {
+                changes.push(format!("pattern {:02} added", index));
+                continue;
+            }
+        };
+        if old_index != index {
+            changes.push(format!("pattern {:02} moved to {:02}", old_index, index));
+        }
+
+        let old_lines = old_patterns[old_index].number_of_lines;
+        if old_lines != pattern.number_of_lines {
+            let mut change = format!("pattern {:02} has {} lines instead of {}", index, pattern.number_of_lines, old_lines);
+            let cut_off = cells_after(old, old_index, pattern.number_of_lines);
+            if cut_off > 0 {
+                change += &format!(", cutting off {} in lines {}-{}", if cut_off == 1 { String::from("1 cell") } else { format!("{} cells", cut_off) },
+                    pattern.number_of_lines, old_lines - 1);
+            }
+            changes.push(change);
+        }
+    }
+    for (index, _) in old_patterns.iter().enumerate().filter(|&(index, _)| !patterns.contains(&Some(index))) {
+        changes.push(format!("pattern {:02} removed", index));
+    }
+
+    let old_sequence: Vec<u32> = old.pattern_sequence.sequence_entries.iter().map(|entry| entry.pattern).collect();
+    let new_sequence: Vec<u32> = new.pattern_sequence.sequence_entries.iter().map(|entry| entry.pattern).collect();
+    changes.extend(sequence_changes(&old_sequence, &new_sequence, &patterns));
+
+    changes
+}
+
+// Counts the cells with something in them from line `first_line` of a
+// pattern on, in the columns that are visible.
+fn cells_after(song: &super::renoise::Song, pattern: usize, first_line: u32) -> usize {
+    let number_of_lines = song.pattern_pool.patterns[pattern].number_of_lines;
+
+    Layout::for_song(song).tracks.iter().map(|track_layout| {
+        let lines = lines_by_index(song, Some(pattern), Some(track_layout.index), number_of_lines);
+        lines[(first_line.min(number_of_lines)) as usize..].iter()
+            .map(|&line| track_layout.columns.iter().filter(|column| cell_text(line, column.kind, column.index).is_some()).count())
+            .sum::<usize>()
+    }).sum()
+}
+
+// Lists the patterns inserted into and removed from the pattern sequence,
+// keeping the longest common subsequence of the two versions in place.
+// Positions playing matching patterns count as the same.
+fn sequence_changes(old: &[u32], new: &[u32], patterns: &[Option<usize>]) -> Vec<String> {
+    let same = |i: usize, j: usize| patterns.get(new[j] as usize) == Some(&Some(old[i] as usize));
+
+    // common[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..]
+    let mut common: Vec<Vec<usize>> = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if same(i, j) { common[i + 1][j + 1] + 1 } else { common[i + 1][j].max(common[i][j + 1]) };
+        }
+    }
+
+    let mut changes: Vec<String> = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && same(i, j) {
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            changes.push(format!("sequence position {:03}: pattern {:02} inserted", j, new[j]));
+            j += 1;
+        } else {
+            changes.push(format!("sequence position {:03}: pattern {:02} removed", i, old[i]));
+            i += 1;
+        }
+    }
+
+    changes
+}
+
+// Compares a pattern cell by cell with the pattern `match_patterns` paired it
+// with, following the layout of the new version.  Patterns and tracks that
+// do not exist in the old version count as empty.
+pub fn pattern_changes(old: &super::renoise::Song, new: &super::renoise::Song, pattern: usize, tracks: &[Option<usize>], patterns: &[Option<usize>]) -> Vec<CellChange> {
+    let pat = &new.pattern_pool.patterns[pattern];
+    let track_indices: Vec<usize> = (0..pat.tracks.len()).collect();
+    let layout = Layout::new(new, &track_indices);
+    let mut changes: Vec<CellChange> = vec![];
+
+    for track_layout in layout.tracks.iter() {
+        let new_lines = lines_by_index(new, Some(pattern), Some(track_layout.index), pat.number_of_lines);
+        let old_lines = lines_by_index(old, patterns[pattern], tracks[track_layout.index], pat.number_of_lines);
+
+        for line in 0..pat.number_of_lines {
+            for column in track_layout.columns.iter() {
+                let old_cell = cell_text(old_lines[line as usize], column.kind, column.index);
+                let new_cell = cell_text(new_lines[line as usize], column.kind, column.index);
+
+                let change = match (&old_cell, &new_cell) {
+                    (None, Some(_)) => Change::Added,
+                    (Some(_), None) => Change::Removed,
+                    (Some(old_cell), Some(new_cell)) if old_cell != new_cell => Change::Modified,
+                    _ => { continue; }
+                };
+                changes.push(CellChange { track: track_layout.index, kind: column.kind, column: column.index, line, change, old_text: old_cell });
+            }
+        }
+    }
+
+    changes
+}
+
+// Looks up the lines of a pattern track by line number, following aliases.
+// Returns no lines for patterns or tracks the song does not have.
+fn lines_by_index(song: &super::renoise::Song, pattern: Option<usize>, track: Option<usize>, number_of_lines: u32) -> Vec<Option<&super::renoise::Line>> {
+    let mut lines: Vec<Option<&super::renoise::Line>> = vec![None; number_of_lines as usize];
+
+    let pattern_track = pattern.zip(track).filter(|&(p, t)| song.pattern_pool.patterns.get(p).is_some_and(|p| t < p.tracks.len()));
+    if let Some((pattern, track)) = pattern_track {
+        for line in song.pattern_track_lines(pattern, track).iter() {
+            if line.index < number_of_lines {
+                lines[line.index as usize] = Some(line);
+            }
+        }
+    }
+
+    lines
+}
+
+// Returns what a cell shows, or None if it is empty.
+fn cell_text(line: Option<&super::renoise::Line>, kind: ColumnKind, index: usize) -> Option<String> {
+    let note_column = line.and_then(|l| l.note_columns.get(index)).and_then(|n| n.as_ref());
+    let effect_column = line.and_then(|l| l.effect_columns.get(index)).and_then(|e| e.as_ref());
+
+    match kind {
+        ColumnKind::Note => note_column.filter(|n| n.note.trim() != "" || n.instrument != "..").map(|n| format!("{}{}", n.note, n.instrument)),
+        ColumnKind::Volume => note_column.filter(|n| n.volume != "..").map(|n| n.volume.clone()),
+        ColumnKind::Panning => note_column.filter(|n| n.panning != "..").map(|n| n.panning.clone()),
+        ColumnKind::Effect => effect_column.filter(|e| e.number.trim() != "").map(|e| format!("{}{}", fx_command(&e.number), e.value))
+    }
+}
+
+// Tells whether the image of a pattern could look different in the new
+// version of a song: the pattern, the lines it shows through aliases, the
+// tracks or the tempo changed.  Also true for patterns the old version does not
+// have.
+pub fn pattern_image_changed(old: &super::renoise::Song, new: &super::renoise::Song, pattern: usize) -> bool {
+    let (old_pattern, new_pattern) = match (old.pattern_pool.patterns.get(pattern), new.pattern_pool.patterns.get(pattern)) {
+        (Some(old_pattern), Some(new_pattern)) => (old_pattern, new_pattern),
+        _ => return true
+    };
+
+    old.global_song_data != new.global_song_data || old.tracks != new.tracks
+        || old_pattern.name != new_pattern.name || old_pattern.number_of_lines != new_pattern.number_of_lines
+        || old_pattern.tracks.len() != new_pattern.tracks.len()
+        || (0..new_pattern.tracks.len()).any(|track| {
+            old_pattern.tracks[track].alias_pattern_index != new_pattern.tracks[track].alias_pattern_index
+                || old.pattern_track_lines(pattern, track) != new.pattern_track_lines(pattern, track)
+        })
+}
+
+// Renders a pattern of the new version of a song with the background of the
+// changed cells coloured by how they changed.
+pub fn render(new: &super::renoise::Song, pattern: usize, changes: &[CellChange]) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    let pat = &new.pattern_pool.patterns[pattern];
+    let track_indices: Vec<usize> = (0..pat.tracks.len()).collect();
+    let layout = Layout::new(new, &track_indices);
+    let note_colors = super::renderer::NoteColors::Default;
+
+    let (width, height) = super::renderer::region_size(new, pattern, &track_indices, 0..pat.number_of_lines, note_colors);
+    let mut imgbuf = image::ImageBuffer::new(width, height);
+
+    for cell in changes.iter() {
+        let track_layout = &layout.tracks[cell.track];
+        let column = match track_layout.columns.iter().find(|c| c.kind == cell.kind && c.index == cell.column) {
+            Some(column) => column,
+            None => { continue; }
+        };
+
+        let highlighted: bool = cell.line % new.global_song_data.lines_per_beat == 0;
+        let color = match cell.change {
+            Change::Added => COLOR_DIFF_ADDED.get(highlighted),
+            Change::Removed => COLOR_DIFF_REMOVED.get(highlighted),
+            Change::Modified => COLOR_DIFF_MODIFIED.get(highlighted)
+        };
+        raster::fill_rect(&mut imgbuf, column.x, HEADER_HEIGHT + cell.line * LINE_HEIGHT, column.width, LINE_HEIGHT, &color);
+    }
+
+    // the pattern is drawn over the highlighted cells, which only sets the
+    // pixels of the text
+    super::renderer::draw_region(&mut imgbuf, new, pattern, &track_indices, 0..pat.number_of_lines, note_colors);
+
+    // removed cells show what was in them instead of the dots
+    for cell in changes.iter().filter(|cell| cell.change == Change::Removed) {
+        let column = match layout.tracks[cell.track].columns.iter().find(|c| c.kind == cell.kind && c.index == cell.column) {
+            Some(column) => column,
+            None => { continue; }
+        };
+        let (y, highlighted) = (HEADER_HEIGHT + cell.line * LINE_HEIGHT, cell.line % new.global_song_data.lines_per_beat == 0);
+        raster::fill_rect(&mut imgbuf, column.x, y, column.width, LINE_HEIGHT, &COLOR_DIFF_REMOVED.get(highlighted));
+        if let Some(text) = &cell.old_text {
+            raster::draw_text(&mut imgbuf, text.bytes(), column.x, y, &COLOR_DEFAULT.get(highlighted));
+        }
+    }
+
+    imgbuf
+}
@@ -0,0 +1,396 @@
+extern crate image;
+extern crate png;
+
+use std::io::Write;
+
+// The formats images can be saved in.  All of them are lossless.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Png,
+    WebP,
+    Bmp,
+    Qoi,
+    Tga
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_lowercase().as_str() {
+            "png" => Some(Format::Png),
+            "webp" => Some(Format::WebP),
+            "bmp" => Some(Format::Bmp),
+            "qoi" => Some(Format::Qoi),
+            "tga" => Some(Format::Tga),
+            _ => None
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::WebP => "webp",
+            Format::Bmp => "bmp",
+            Format::Qoi => "qoi",
+            Format::Tga => "tga"
+        }
+    }
+}
+
+// Describes where an image came from, as keyword/value pairs.  Only PNG has a
+// place for them, and gets one text chunk per pair.
+pub type Metadata = [(&'static str, String)];
+
+// Returns the metadata every image of a song carries.
+pub fn song_metadata(song: &super::renoise::Song) -> Vec<(&'static str, String)> {
+    vec![
+        ("Title", song.global_song_data.song_name.clone()),
+        ("Author", song.global_song_data.artist.clone()),
+        ("Software", format!("patren {}", env!("CARGO_PKG_VERSION")))
+    ]
+}
+
+pub fn save(image: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, format: Format, metadata: &Metadata, path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    let file = std::fs::File::create(path)?;
+    let mut out = std::io::BufWriter::new(file);
+
+    write(&mut out, image, format, metadata)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+pub fn write<W: Write>(out: W, image: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, format: Format, metadata: &Metadata) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        Format::Png => write_png(out, image, metadata),
+        Format::WebP => super::webp::encode(out, image),
+        Format::Bmp => {
+            let mut out = out;
+            image::bmp::BMPEncoder::new(&mut out).encode(image, image.width(), image.height(), image::ColorType::RGBA(8))?;
+            Ok(())
+        },
+        Format::Qoi => write_qoi(out, image),
+        Format::Tga => write_tga(out, image)
+    }
+}
+
+fn write_png<W: Write>(out: W, image: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, metadata: &Metadata) -> Result<(), Box<dyn std::error::Error>> {
+    let mut encoder = png::Encoder::new(out, image.width(), image.height());
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    for (keyword, text) in metadata.iter() {
+        let (chunk_type, data) = text_chunk(keyword, text);
+        writer.write_chunk(chunk_type, &data)?;
+    }
+    writer.write_image_data(image)?;
+
+    Ok(())
+}
+
+// Builds a text chunk and returns its type and data.  tEXt holds Latin-1
+// text, anything else goes into an uncompressed iTXt chunk as UTF-8.
+fn text_chunk(keyword: &str, text: &str) -> ([u8; 4], Vec<u8>) {
+    let mut data: Vec<u8> = keyword.bytes().collect();
+    data.push(0);
+
+    if text.chars().all(|c| (c as u32) < 0x100) {
+        data.extend(text.chars().map(|c| c as u8));
+        (*b"tEXt", data)
+    } else {
+        data.extend_from_slice(&[0, 0, 0, 0]); // not compressed, no language tag or translated keyword
+        data.extend_from_slice(text.as_bytes());
+        (*b"iTXt", data)
+    }
+}
+
+// Writes the image in the Quite OK Image format (https://qoiformat.org).
+fn write_qoi<W: Write>(mut out: W, image: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut data: Vec<u8> = b"qoif".to_vec();
+    data.extend_from_slice(&image.width().to_be_bytes());
+    data.extend_from_slice(&image.height().to_be_bytes());
+    data.extend_from_slice(&[4, 0]); // RGBA, sRGB
+
+    let mut seen: [[u8; 4]; 64] = [[0; 4]; 64];
+    let mut previous: [u8; 4] = [0, 0, 0, 255];
+    let mut run: u8 = 0;
+    let number_of_pixels = image.pixels().len();
+
+    for (position, pixel) in image.pixels().enumerate() {
+        let [r, g, b, a] = pixel.0;
+
+        if pixel.0 == previous {
+            run += 1;
+            if run == 62 || position == number_of_pixels - 1 {
+                data.push(0xc0 | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+
+        if run > 0 {
+            data.push(0xc0 | (run - 1));
+            run = 0;
+        }
+
+        let hash = (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64;
+        if seen[hash] == pixel.0 {
+            data.push(hash as u8);
+        } else if a == previous[3] {
+            let dr = r.wrapping_sub(previous[0]) as i8;
+            let dg = g.wrapping_sub(previous[1]) as i8;
+            let db = b.wrapping_sub(previous[2]) as i8;
+            let (dr_dg, db_dg) = (dr.wrapping_sub(dg), db.wrapping_sub(dg));
+
+            if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                data.push(0x40 | ((dr + 2) << 4 | (dg + 2) << 2 | (db + 2)) as u8);
+            } else if (-32..=31).contains(&dg) && (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg) {
+                data.push(0x80 | (dg + 32) as u8);
+                data.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+            } else {
+                data.extend_from_slice(&[0xfe, r, g, b]);
+            }
+        } else {
+            data.extend_from_slice(&[0xff, r, g, b, a]);
+        }
+
+        seen[hash] = pixel.0;
+        previous = pixel.0;
+    }
+
+    data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    out.write_all(&data)?;
+
+    Ok(())
+}
+
+// Writes the image as a run-length encoded TGA with 32 bits per pixel, stored
+// from the top row down.
+fn write_tga<W: Write>(mut out: W, image: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> Result<(), Box<dyn std::error::Error>> {
+    let (width, height) = image.dimensions();
+    if width > 0xffff || height > 0xffff {
+        bail!("TGA images can be at most 65535 pixels wide and high, not {}x{}", width, height);
+    }
+
+    let mut data: Vec<u8> = vec![0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0]; // no ID, no colour map, RLE true colour
+    data.extend_from_slice(&(width as u16).to_le_bytes());
+    data.extend_from_slice(&(height as u16).to_le_bytes());
+    data.extend_from_slice(&[32, 0x28]); // 8 bits of alpha, top left origin
+
+    // packets do not span rows
+    for row in image.rows() {
+        let pixels: Vec<[u8; 4]> = row.map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]]).collect();
+        let mut position = 0;
+        while position < pixels.len() {
+            let run = pixels[position..].iter().take(128).take_while(|&&pixel| pixel == pixels[position]).count();
+            if run > 1 {
+                data.push(0x80 | (run - 1) as u8);
+                data.extend_from_slice(&pixels[position]);
+                position += run;
+                continue;
+            }
+
+            // raw pixels up to the next run
+            let mut count = 1;
+            while position + count < pixels.len() && count < 128 && (position + count + 1 == pixels.len() || pixels[position + count] != pixels[position + count + 1]) {
+                count += 1;
+            }
+            data.push((count - 1) as u8);
+            for pixel in pixels[position..position + count].iter() {
+                data.extend_from_slice(pixel);
+            }
+            position += count;
+        }
+    }
+
+    out.write_all(&data)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate image_webp;
+
+    use super::*;
+
+    // An image with what rendered patterns have, long runs of the background
+    // and text in a few colours, and what they do not, noise and
+    // transparency, of a size that is no multiple of anything.
+    fn test_image() -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+        let mut seed: u32 = 1;
+        image::ImageBuffer::from_fn(157, 43, |x, y| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            match (x / 40, y % 10) {
+                (0, _) => image::Rgba([0x20, 0x20, 0x20, 0xff]),
+                (1, row) if row < 8 && (x + y) % 3 == 0 => image::Rgba([0xe0, 0xd0, 0x40, 0xff]),
+                (1, _) => image::Rgba([0x20, 0x20, 0x20, 0xff]),
+                (2, _) => image::Rgba([(seed >> 24) as u8, (seed >> 16) as u8, (seed >> 8) as u8, 0xff]),
+                _ => image::Rgba([x as u8, y as u8, (x * y) as u8, (seed >> 24) as u8])
+            }
+        })
+    }
+
+    fn encode(image: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, format: Format, metadata: &Metadata) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![];
+        write(&mut data, image, format, metadata).unwrap();
+        data
+    }
+
+    // Decoders for the formats no crate at hand reads, returning the size
+    // and the RGBA pixels.
+    fn decode_qoi(data: &[u8]) -> (u32, u32, Vec<u8>) {
+        assert_eq!(&data[..4], b"qoif");
+        let width = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        let height = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+        assert_eq!(&data[data.len() - 8..], &[0, 0, 0, 0, 0, 0, 0, 1]);
+
+        let mut pixels: Vec<u8> = vec![];
+        let mut seen: [[u8; 4]; 64] = [[0; 4]; 64];
+        let mut pixel: [u8; 4] = [0, 0, 0, 255];
+        let mut position = 14;
+        while pixels.len() < (width * height * 4) as usize {
+            let tag = data[position];
+            position += 1;
+            let mut run = 1;
+            match tag {
+                0xfe => { pixel[..3].copy_from_slice(&data[position..position + 3]); position += 3; },
+                0xff => { pixel.copy_from_slice(&data[position..position + 4]); position += 4; },
+                _ => match tag >> 6 {
+                    0 => pixel = seen[tag as usize],
+                    1 => for (channel, shift) in [4, 2, 0].iter().enumerate() {
+                        pixel[channel] = pixel[channel].wrapping_add((tag >> shift) & 3).wrapping_sub(2);
+                    },
+                    2 => {
+                        let dg = (tag & 0x3f).wrapping_sub(32);
+                        let next = data[position];
+                        position += 1;
+                        pixel[0] = pixel[0].wrapping_add(dg).wrapping_add(next >> 4).wrapping_sub(8);
+                        pixel[1] = pixel[1].wrapping_add(dg);
+                        pixel[2] = pixel[2].wrapping_add(dg).wrapping_add(next & 0xf).wrapping_sub(8);
+                    },
+                    _ => run = (tag & 0x3f) as usize + 1
+                }
+            }
+            let [r, g, b, a] = pixel;
+            seen[(r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64] = pixel;
+            for _ in 0..run {
+                pixels.extend_from_slice(&pixel);
+            }
+        }
+        assert_eq!(position, data.len() - 8);
+
+        (width, height, pixels)
+    }
+
+    fn decode_tga(data: &[u8]) -> (u32, u32, Vec<u8>) {
+        assert_eq!(&data[..12], &[0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let width = u16::from_le_bytes([data[12], data[13]]) as u32;
+        let height = u16::from_le_bytes([data[14], data[15]]) as u32;
+        assert_eq!(&data[16..18], &[32, 0x28]);
+
+        let mut pixels: Vec<u8> = vec![];
+        let mut position = 18;
+        while position < data.len() {
+            let header = data[position];
+            let count = (header & 0x7f) as usize + 1;
+            position += 1;
+            if header & 0x80 != 0 {
+                for _ in 0..count {
+                    pixels.extend_from_slice(&[data[position + 2], data[position + 1], data[position], data[position + 3]]);
+                }
+                position += 4;
+            } else {
+                for pixel in data[position..position + count * 4].chunks_exact(4) {
+                    pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                }
+                position += count * 4;
+            }
+        }
+
+        (width, height, pixels)
+    }
+
+    // The type and data of the chunks of a PNG file.
+    fn png_chunks(data: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        let mut chunks = vec![];
+        let mut position = 8;
+        while position < data.len() {
+            let length = u32::from_be_bytes([data[position], data[position + 1], data[position + 2], data[position + 3]]) as usize;
+            let chunk_type = [data[position + 4], data[position + 5], data[position + 6], data[position + 7]];
+            chunks.push((chunk_type, data[position + 8..position + 8 + length].to_vec()));
+            position += 12 + length;
+        }
+        chunks
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let image = test_image();
+        let data = encode(&image, Format::Png, &[]);
+
+        let (info, mut reader) = png::Decoder::new(&data[..]).read_info().unwrap();
+        let mut pixels = vec![0; info.buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height, info.color_type), (157, 43, png::ColorType::RGBA));
+        assert_eq!(pixels, image.into_raw());
+    }
+
+    #[test]
+    fn png_text() {
+        let metadata = [("Title", String::from("Café")), ("Author", String::from("Ääkköset 日本"))];
+        let data = encode(&test_image(), Format::Png, &metadata);
+
+        let texts: Vec<([u8; 4], Vec<u8>)> = png_chunks(&data).into_iter().filter(|(t, _)| t == b"tEXt" || t == b"iTXt").collect();
+        assert_eq!(texts, vec![
+            (*b"tEXt", b"Title\0Caf\xe9".to_vec()),
+            (*b"iTXt", [&b"Author\0\0\0\0\0"[..], "Ääkköset 日本".as_bytes()].concat())
+        ]);
+    }
+
+    #[test]
+    fn bmp_round_trip() {
+        let image = test_image();
+        let data = encode(&image, Format::Bmp, &[]);
+
+        let decoded = image::load_from_memory_with_format(&data, image::ImageFormat::BMP).unwrap().to_rgba();
+        assert_eq!(decoded.dimensions(), image.dimensions());
+        assert_eq!(decoded.into_raw(), image.into_raw());
+    }
+
+    #[test]
+    fn qoi_round_trip() {
+        let image = test_image();
+        assert_eq!(decode_qoi(&encode(&image, Format::Qoi, &[])), (157, 43, image.into_raw()));
+    }
+
+    #[test]
+    fn tga_round_trip() {
+        let image = test_image();
+        assert_eq!(decode_tga(&encode(&image, Format::Tga, &[])), (157, 43, image.into_raw()));
+    }
+
+    #[test]
+    fn webp_round_trip() {
+        let image = test_image();
+        let data = encode(&image, Format::WebP, &[]);
+
+        let mut decoder = image_webp::WebPDecoder::new(std::io::Cursor::new(data)).unwrap();
+        assert_eq!((decoder.dimensions(), decoder.has_alpha()), ((157, 43), true));
+        let mut pixels = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut pixels).unwrap();
+        assert_eq!(pixels, image.into_raw());
+    }
+
+    #[test]
+    fn webp_opaque_round_trip() {
+        let image = image::ImageBuffer::from_fn(300, 7, |x, y| image::Rgba([(x / 7) as u8, y as u8, 0x40, 0xff]));
+        let data = encode(&image, Format::WebP, &[]);
+
+        let mut decoder = image_webp::WebPDecoder::new(std::io::Cursor::new(data)).unwrap();
+        let mut pixels = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut pixels).unwrap();
+        let expected: Vec<u8> = if decoder.has_alpha() { image.into_raw() } else { image.pixels().flat_map(|p| p.0[..3].to_vec()).collect() };
+        assert_eq!(pixels, expected);
+    }
+}
@@ -0,0 +1,119 @@
+// How playback moves through the patterns of the sequence.  A ZB (pattern
+// break) command ends a pattern after its line and continues at the given line
+// of the next pattern, and a ZD (delay pattern) command holds its line for the
+// given number of lines.
+
+// The flow through the pattern played at a position of the pattern sequence.
+#[derive(Clone, PartialEq, Debug)]
+pub struct EntryFlow {
+    pub first_line: u32,                 // where playback enters the pattern
+    pub pattern_break: Option<(u32, u32)>, // line of the ZB command and the line of the next pattern it jumps to
+    pub delays: Vec<(u32, u32)>          // line of each ZD command and the number of lines
+}
+
+impl EntryFlow {
+    pub fn is_played(&self, line: u32) -> bool {
+        line >= self.first_line && self.pattern_break.is_none_or(|(break_line, _)| line <= break_line)
+    }
+}
+
+// Follows the pattern breaks from the start of the song, returning the flow
+// of every position of the pattern sequence.
+pub fn sequence_flow(song: &super::renoise::Song) -> Vec<EntryFlow> {
+    let mut first_line: u32 = 0;
+
+    song.pattern_sequence.sequence_entries.iter().map(|entry| {
+        let pattern = entry.pattern as usize;
+        let pat = &song.pattern_pool.patterns[pattern];
+        first_line = first_line.min(pat.number_of_lines.saturating_sub(1));
+
+        // the flow commands of all tracks, by line
+        let mut commands: Vec<(u32, &str, u32)> = vec![];
+        for track_index in 0..pat.tracks.len() {
+            for line in song.pattern_track_lines(pattern, track_index).iter().filter(|l| l.index < pat.number_of_lines) {
+                for effect in line.effect_columns.iter().flatten().filter(|e| e.number == "ZB" || e.number == "ZD") {
+                    commands.push((line.index, &effect.number, u32::from_str_radix(&effect.value, 16).unwrap_or(0)));
+                }
+            }
+        }
+        commands.sort_unstable();
+
+        // breaks before the line playback enters at are never reached, and
+        // neither are delays outside the lines played
+        let pattern_break = commands.iter()
+            .find(|&&(line, number, _)| number == "ZB" && line >= first_line)
+            .map(|&(line, _, target)| (line, target));
+        let mut flow = EntryFlow { first_line, pattern_break, delays: vec![] };
+        flow.delays = commands.iter()
+            .filter(|&&(line, number, lines)| number == "ZD" && lines > 0 && flow.is_played(line))
+            .map(|&(line, _, lines)| (line, lines))
+            .collect();
+
+        first_line = pattern_break.map_or(0, |(_, target)| target);
+        flow
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::renoise::{Song, TestSong};
+
+    // A pattern, given by its number of lines and its flow commands, as line,
+    // command and value.
+    type TestPattern<'a> = (u32, &'a [(u32, &'static str, &'static str)]);
+
+    // A song with a single track, playing its patterns in order.
+    fn song(patterns: &[TestPattern]) -> Song {
+        let mut song = TestSong::default().track("Track");
+        for (index, &(number_of_lines, commands)) in patterns.iter().enumerate() {
+            song = song.pattern("", number_of_lines).sequence(index, "");
+            for &(line, number, value) in commands.iter() {
+                song = song.effect(0, line, number, value);
+            }
+        }
+        song.song()
+    }
+
+    #[test]
+    fn pattern_break() {
+        // the break in the second pattern comes before the line it is entered
+        // at, and is never reached
+        let flows = sequence_flow(&song(&[(64, &[(15, "ZB", "08")]), (64, &[(4, "ZB", "00")]), (64, &[])]));
+
+        assert_eq!(flows, vec![
+            EntryFlow { first_line: 0, pattern_break: Some((15, 8)), delays: vec![] },
+            EntryFlow { first_line: 8, pattern_break: None, delays: vec![] },
+            EntryFlow { first_line: 0, pattern_break: None, delays: vec![] }
+        ]);
+        assert!(flows[0].is_played(15) && !flows[0].is_played(16));
+        assert!(!flows[1].is_played(7) && flows[1].is_played(8) && flows[1].is_played(63));
+    }
+
+    #[test]
+    fn pattern_break_into_shorter_pattern() {
+        // playback enters the last line of a pattern too short for the line
+        // the break jumps to
+        let flows = sequence_flow(&song(&[(64, &[(3, "ZB", "20")]), (16, &[]), (16, &[])]));
+
+        assert_eq!(flows.iter().map(|flow| flow.first_line).collect::<Vec<u32>>(), vec![0, 15, 0]);
+        assert!(!flows[1].is_played(14) && flows[1].is_played(15));
+    }
+
+    #[test]
+    fn delay() {
+        let flows = sequence_flow(&song(&[(16, &[(4, "ZD", "03"), (8, "ZD", "00"), (12, "ZD", "0A")])]));
+
+        assert_eq!(flows, vec![EntryFlow { first_line: 0, pattern_break: None, delays: vec![(4, 3), (12, 10)] }]);
+        assert!((0..16).all(|line| flows[0].is_played(line)));
+
+        // the second pattern is entered at line 8 and left after line 12, so
+        // only the delays on lines 8 to 12 are played
+        let flows = sequence_flow(&song(&[
+            (16, &[(15, "ZB", "08")]),
+            (16, &[(3, "ZD", "02"), (8, "ZD", "01"), (12, "ZB", "00"), (12, "ZD", "04"), (14, "ZD", "05")])
+        ]));
+
+        assert_eq!(flows[1], EntryFlow { first_line: 8, pattern_break: Some((12, 0)), delays: vec![(8, 1), (12, 4)] });
+    }
+}
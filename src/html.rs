@@ -0,0 +1,193 @@
+extern crate image;
+
+use std::fmt::Write;
+
+use super::layout::{ColumnKind, Layout, TrackLayout};
+use super::theme::*;
+
+// Renders a pattern as a page linking back to the index at `index_href`, a
+// relative path given as its components.
+pub fn render(song: &super::renoise::Song, pattern: usize, index_href: &[String]) -> String {
+    let pat = &song.pattern_pool.patterns[pattern];
+    let mut html = String::new();
+
+    write_header(&mut html, song, &format!("Pattern {:02}", pattern));
+
+    writeln!(html, "<p><a href=\"{}\">Index</a></p>", href(index_href)).unwrap();
+    writeln!(html, "<table>").unwrap();
+
+    // track names
+    write!(html, "<thead><tr><th></th>").unwrap();
+    for (index, track_info) in song.tracks.iter().take(pat.tracks.len()).enumerate() {
+        let [r, g, b] = track_info.rgb();
+        write!(html, "<th style=\"border-top-color: rgb({}, {}, {})\">{}", r, g, b, escape(&track_info.name)).unwrap();
+        if pat.tracks[index].alias_pattern_index >= 0 {
+            write!(html, " <span class=\"alias\">&rarr; pattern {:02}</span>", pat.tracks[index].alias_pattern_index).unwrap();
+        }
+        write!(html, "</th>").unwrap();
+    }
+    writeln!(html, "</tr></thead>").unwrap();
+
+    // collect the lines of each track, so that each row can be written out in one go
+    let tracks: Vec<Vec<Option<&super::renoise::Line>>> = (0..pat.tracks.len()).map(|index| {
+        let mut lines = vec![None; pat.number_of_lines as usize];
+        for line in song.pattern_track_lines(pattern, index).iter() {
+            if line.index < pat.number_of_lines {
+                lines[line.index as usize] = Some(line);
+            }
+        }
+        lines
+    }).collect();
+
+    let track_indices: Vec<usize> = (0..pat.tracks.len()).collect();
+    let layout = Layout::new(song, &track_indices);
+
+    writeln!(html, "<tbody>").unwrap();
+    for line in 0..pat.number_of_lines {
+        let highlighted: bool = line % song.global_song_data.lines_per_beat == 0;
+
+        write!(html, "<tr{}><td class=\"line\">{:02X}</td>", if highlighted { " class=\"hl\"" } else { "" }, line).unwrap();
+        for (track_layout, lines) in layout.tracks.iter().zip(tracks.iter()) {
+            write!(html, "<td>").unwrap();
+            write_line(&mut html, lines[line as usize], track_layout, highlighted);
+            write!(html, "</td>").unwrap();
+        }
+        writeln!(html, "</tr>").unwrap();
+    }
+    writeln!(html, "</tbody>").unwrap();
+
+    writeln!(html, "</table>").unwrap();
+    write_footer(&mut html);
+
+    html
+}
+
+// Renders the pattern sequence as a list linking to the pages of the patterns
+// at `pattern_hrefs`, one relative path per pattern of the pool.
+pub fn render_index(song: &super::renoise::Song, pattern_hrefs: &[Vec<String>]) -> String {
+    let mut html = String::new();
+
+    write_header(&mut html, song, "Index");
+
+    writeln!(html, "<ol start=\"0\">").unwrap();
+    for entry in song.pattern_sequence.sequence_entries.iter() {
+        write!(html, "<li><a href=\"{}\">Pattern {:02}</a>", href(&pattern_hrefs[entry.pattern as usize]), entry.pattern).unwrap();
+        if !entry.section_name.is_empty() {
+            write!(html, " &ndash; {}", escape(&entry.section_name)).unwrap();
+        }
+        writeln!(html, "</li>").unwrap();
+    }
+    writeln!(html, "</ol>").unwrap();
+
+    write_footer(&mut html);
+
+    html
+}
+
+fn write_header(html: &mut String, song: &super::renoise::Song, title: &str) {
+    let song_data = &song.global_song_data;
+
+    writeln!(html, "<!DOCTYPE html>").unwrap();
+    writeln!(html, "<html>").unwrap();
+    writeln!(html, "<head>").unwrap();
+    writeln!(html, "<meta charset=\"utf-8\">").unwrap();
+    writeln!(html, "<title>{} &ndash; {}</title>", escape(&song_data.song_name), title).unwrap();
+    writeln!(html, "<style>").unwrap();
+    writeln!(html, "body {{ background: {}; color: {}; font-family: monospace; }}", css(&COLOR_BACK.normal), css(&COLOR_DEFAULT.normal)).unwrap();
+    writeln!(html, "a {{ color: {}; }}", css(&COLOR_DEFAULT.highlighted)).unwrap();
+    writeln!(html, "table {{ border-collapse: collapse; }}").unwrap();
+    writeln!(html, "th {{ border-top: 3px solid; padding: 0 6px; text-align: left; }}").unwrap();
+    writeln!(html, "td {{ padding: 0 6px; white-space: pre; }}").unwrap();
+    writeln!(html, "tr.hl {{ background: {}; color: {}; }}", css(&COLOR_BACK.highlighted), css(&COLOR_DEFAULT.highlighted)).unwrap();
+    writeln!(html, "[title] {{ cursor: help; }}").unwrap();
+    writeln!(html, ".alias {{ color: {}; }}", css(&COLOR_ALIAS.normal)).unwrap();
+    for (class, color) in [("vol", &COLOR_VOLUME), ("pan", &COLOR_PANNING)].iter() {
+        writeln!(html, ".{} {{ color: {}; }}", class, css(&color.normal)).unwrap();
+        writeln!(html, "tr.hl .{} {{ color: {}; }}", class, css(&color.highlighted)).unwrap();
+    }
+    writeln!(html, "</style>").unwrap();
+    writeln!(html, "</head>").unwrap();
+    writeln!(html, "<body>").unwrap();
+    writeln!(html, "<h1>{} by {}</h1>", escape(&song_data.song_name), escape(&song_data.artist)).unwrap();
+    writeln!(html, "<h2>{}</h2>", title).unwrap();
+}
+
+fn write_footer(html: &mut String) {
+    writeln!(html, "</body>").unwrap();
+    writeln!(html, "</html>").unwrap();
+}
+
+fn write_line(html: &mut String, line: Option<&super::renoise::Line>, track_layout: &TrackLayout, highlighted: bool) {
+    for column in track_layout.columns.iter() {
+        let note_column = line.and_then(|l| l.note_columns.get(column.index)).and_then(|n| n.as_ref());
+
+        match (column.kind, note_column) {
+            (ColumnKind::Note, Some(note)) => {
+                if column.index > 0 {
+                    write!(html, " ").unwrap();
+                }
+                write!(html, "{}", escape(&note.note)).unwrap();
+                if note.instrument == ".." {
+                    write!(html, "..").unwrap();
+                } else {
+                    write!(html, "<span title=\"Instrument {}\">{}</span>", escape(&note.instrument), escape(&note.instrument)).unwrap();
+                }
+            },
+            (ColumnKind::Note, None) => {
+                if column.index > 0 {
+                    write!(html, " ").unwrap();
+                }
+                write!(html, "   ..").unwrap();
+            },
+            (ColumnKind::Volume, note) => {
+                write!(html, " <span class=\"vol\">{}</span>", escape(note.map_or("..", |n| &n.volume))).unwrap();
+            },
+            (ColumnKind::Panning, note) => {
+                write!(html, " <span class=\"pan\">{}</span>", escape(note.map_or("..", |n| &n.panning))).unwrap();
+            },
+            (ColumnKind::Effect, _) => {
+                write!(html, " ").unwrap();
+
+                match line.and_then(|l| l.effect_columns.get(column.index)).and_then(|e| e.as_ref()) {
+                    Some(effect) => {
+                        write!(
+                            html,
+                            "<span style=\"color: {}\" title=\"{}\">{}{}</span>",
+                            css(&fx_color(&effect.number).get(highlighted)),
+                            escape(&effect.description()),
+                            escape(&fx_command(&effect.number)),
+                            escape(&effect.value)
+                        ).unwrap();
+                    },
+                    None => {
+                        write!(html, "    ").unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn css(color: &image::Rgba<u8>) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+// Turns the components of a relative path into a URL, escaping what is not
+// allowed in one.
+fn href(components: &[String]) -> String {
+    let encoded: Vec<String> = components.iter().map(|component| {
+        component.bytes().map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte)
+        }).collect()
+    }).collect();
+
+    escape(&encoded.join("/"))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
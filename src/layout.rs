@@ -0,0 +1,140 @@
+use std::ops::Range;
+
+const CHAR_WIDTH:            u32 = super::pattern_font::CHAR_WIDTH as u32;
+pub const TRACK_SPACING_X:   u32 = 6;
+const COLUMN_SPACING_X:      u32 = 3;
+const COLUMN_WIDTH_NOTE:     u32 = COLUMN_SPACING_X + 5 * CHAR_WIDTH; // e.g. C-500
+const COLUMN_WIDTH_VOL:      u32 = COLUMN_SPACING_X + 2 * CHAR_WIDTH; // e.g. 7F
+const COLUMN_WIDTH_FX:       u32 = COLUMN_SPACING_X + 4 * CHAR_WIDTH; // e.g. ZT04
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColumnKind {
+    Note,    // note and instrument
+    Volume,
+    Panning,
+    Effect
+}
+
+// A column of a track.  `index` is the number of the note column (or of the
+// effect column) it belongs to, `x` is relative to the left edge of the
+// layout, and `width` includes the spacing after the column.
+pub struct Column {
+    pub kind: ColumnKind,
+    pub index: usize,
+    pub x: u32,
+    pub width: u32
+}
+
+pub struct TrackLayout {
+    pub index: usize,
+    pub x: u32,
+    pub width: u32, // without the spacing between tracks
+    pub columns: Vec<Column>
+}
+
+// Where the tracks and their columns are placed horizontally, computed once
+// and shared by everything that needs to know.
+pub struct Layout {
+    pub tracks: Vec<TrackLayout>,
+    pub width: u32
+}
+
+impl Layout {
+    // Lays out the given tracks next to each other, in the order they are
+    // given.
+    pub fn new(song: &super::renoise::Song, tracks: &[usize]) -> Layout {
+        let mut track_layouts: Vec<TrackLayout> = Vec::with_capacity(tracks.len());
+        let mut x: u32 = 0;
+
+        for &index in tracks.iter() {
+            let track_layout = layout_track(&song.tracks[index], index, x);
+            x += track_layout.width + TRACK_SPACING_X;
+            track_layouts.push(track_layout);
+        }
+
+        Layout {
+            tracks: track_layouts,
+            width: x
+        }
+    }
+
+    pub fn for_song(song: &super::renoise::Song) -> Layout {
+        let tracks: Vec<usize> = (0..song.tracks.len()).collect();
+
+        Layout::new(song, &tracks)
+    }
+
+    // Returns the track and column at the horizontal position `x`, e.g. to
+    // map a position in a rendered image back to the pattern.  The column is
+    // None in the spacing between tracks.
+    pub fn column_at(&self, x: u32) -> Option<(&TrackLayout, Option<&Column>)> {
+        let position = self.tracks.partition_point(|track| track.x <= x).checked_sub(1)?;
+        let track = &self.tracks[position];
+        if x >= track.x + track.width + TRACK_SPACING_X {
+            return None;
+        }
+
+        let column = track.columns.iter().find(|column| column_range(column).contains(&x));
+        Some((track, column))
+    }
+}
+
+fn column_range(column: &Column) -> Range<u32> {
+    column.x..column.x + column.width
+}
+
+fn layout_track(track_info: &super::renoise::Track, index: usize, x: u32) -> TrackLayout {
+    let mut columns: Vec<Column> = vec![];
+    let mut column_x: u32 = x;
+    let mut push = |kind: ColumnKind, index: usize, width: u32| {
+        columns.push(Column { kind, index, x: column_x, width });
+        column_x += width;
+    };
+
+    for note_index in 0..track_info.number_of_visible_note_columns as usize {
+        push(ColumnKind::Note, note_index, COLUMN_WIDTH_NOTE);
+        if track_info.volume_column_is_visible {
+            push(ColumnKind::Volume, note_index, COLUMN_WIDTH_VOL);
+        }
+        if track_info.panning_column_is_visible {
+            push(ColumnKind::Panning, note_index, COLUMN_WIDTH_VOL);
+        }
+    }
+    for effect_index in 0..track_info.number_of_visible_effect_columns as usize {
+        push(ColumnKind::Effect, effect_index, COLUMN_WIDTH_FX);
+    }
+
+    TrackLayout {
+        index,
+        x,
+        width: column_x - x,
+        columns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::renoise::TestSong;
+
+    #[test]
+    fn column_at() {
+        let song = TestSong::default().track("Drums").track("Bass").song();
+        let layout = Layout::for_song(&song);
+        let hit = |x: u32| layout.column_at(x).map(|(track, column)| (track.index, column.map(|c| (c.kind, c.index))));
+
+        let drums = &layout.tracks[0];
+        let effect_x = drums.columns[1].x;
+        assert_eq!(hit(0), Some((0, Some((ColumnKind::Note, 0)))));
+        assert_eq!(hit(effect_x - 1), Some((0, Some((ColumnKind::Note, 0)))));
+        assert_eq!(hit(effect_x), Some((0, Some((ColumnKind::Effect, 0)))));
+        // the spacing between the tracks belongs to the track on its left
+        assert_eq!(hit(drums.width), Some((0, None)));
+        assert_eq!(hit(layout.tracks[1].x), Some((1, Some((ColumnKind::Note, 0)))));
+        assert_eq!(hit(layout.width), None);
+
+        // tracks laid out on their own keep their index
+        let bass = Layout::new(&song, &[1]);
+        assert_eq!(bass.column_at(0).map(|(track, _)| track.index), Some(1));
+    }
+}
@@ -0,0 +1,23 @@
+#[macro_use]
+extern crate simple_error;
+
+pub mod renoise;
+pub mod pattern_font;
+pub mod theme;
+pub mod layout;
+pub mod raster;
+pub mod canvas;
+pub mod flow;
+pub mod renderer;
+pub mod html;
+pub mod matrix;
+pub mod piano_roll;
+pub mod thumbnail;
+pub mod contact_sheet;
+pub mod animation;
+pub mod diff;
+pub mod pdf;
+pub mod webp;
+pub mod export;
+pub mod print;
+pub mod name_template;
@@ -1,29 +1,1229 @@
-#[macro_use]
-extern crate simple_error;
+extern crate clap;
+extern crate glob;
+extern crate notify;
+extern crate patren;
+extern crate rayon;
 
-mod renoise;
-mod pattern_font;
-mod renderer;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use patren::{animation, contact_sheet, diff, export, html, layout, matrix, piano_roll, print, renderer, renoise, thumbnail};
+use patren::name_template::{NameTemplate, Subject};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const EXIT_FAILURE: i32 = 1; // rendering or writing the output failed
+const EXIT_USAGE:   i32 = 2; // the arguments make no sense
+const EXIT_INPUT:   i32 = 3; // a song could not be read
+
+// How long no file system events must come in before `render --watch` reads
+// the saved song, leaving Renoise time to write it and rename it into place.
+const WATCH_SETTLE_TIME: std::time::Duration = std::time::Duration::from_millis(500);
+
+// Why patren gave up, which decides the exit code.  The messages are ready to
+// be printed, and can be sent between the threads of `run_jobs`.
+enum Failure {
+    Usage(String),
+    Input(String),
+    Output(String)
+}
+
+impl Failure {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Usage(_) => EXIT_USAGE,
+            Failure::Input(_) => EXIT_INPUT,
+            Failure::Output(_) => EXIT_FAILURE
+        }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Failure::Usage(message) | Failure::Input(message) | Failure::Output(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl From<Box<dyn std::error::Error>> for Failure {
+    fn from(error: Box<dyn std::error::Error>) -> Failure {
+        Failure::Output(error.to_string())
+    }
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(error: std::io::Error) -> Failure {
+        Failure::Output(error.to_string())
+    }
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let matches = match app().get_matches_safe() {
+        Ok(matches) => matches,
+        Err(error) if error.use_stderr() => {
+            eprintln!("{}", error.message);
+            std::process::exit(EXIT_USAGE);
+        },
+        Err(error) => error.exit() // --help and --version
+    };
+
+    if let Err(failure) = run(&matches) {
+        eprintln!("error: {}", failure);
+        std::process::exit(failure.exit_code());
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    let files = || Arg::with_name("FILE").help("Renoise song (.xrns)").required(true).multiple(true);
+    let patterns = || Arg::with_name("patterns").long("patterns").value_name("LIST").help("Only these patterns, e.g. 0-5,8");
+    let color_by_instrument = || Arg::with_name("color-by-instrument").long("color-by-instrument").help("Colours notes by instrument, with a legend");
+    let hide_muted = || Arg::with_name("hide-muted").long("hide-muted").help("Leaves out tracks muted in a sequence slot instead of dimming them");
+    let flow = || Arg::with_name("flow").long("flow").help("Annotates pattern breaks (ZB) and delays (ZD) in a margin");
+
+    App::new("patren")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Renders the patterns of Renoise songs")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(Arg::with_name("output-dir").short("o").long("output-dir").value_name("DIRECTORY").global(true)
+            .help("Where to write the output (default: the current directory)"))
+        .arg(Arg::with_name("format").short("f").long("format").value_name("FORMAT").global(true)
+            .possible_values(&["png", "webp", "bmp", "qoi", "tga"]).help("Image format (default: png)"))
+        .arg(Arg::with_name("jobs").short("j").long("jobs").value_name("N").global(true)
+            .help("Number of threads (default: one per CPU core)"))
+        .arg(Arg::with_name("name-template").long("name-template").value_name("TEMPLATE").global(true)
+            .help("Names of the output files, e.g. {song}/{pattern}-{pattern_name}.{ext}"))
+        .subcommand(SubCommand::with_name("render").about("Renders an image per pattern")
+            .arg(files()).arg(patterns()).arg(color_by_instrument())
+            .arg(Arg::with_name("tracks").long("tracks").value_name("LIST").help("Only these tracks, by index, range or name, e.g. 0-1,Drum*"))
+            .arg(Arg::with_name("lines").long("lines").value_name("FIRST-LAST").help("Only these lines, e.g. 16-31"))
+            .arg(Arg::with_name("watch").long("watch").help("Keeps running and renders the patterns again whenever the song is saved")))
+        .subcommand(SubCommand::with_name("sequence").about("Renders an image per sequence position, as heard")
+            .arg(files()).arg(hide_muted()).arg(color_by_instrument()).arg(flow()))
+        .subcommand(SubCommand::with_name("song").about("Renders the whole song in play order")
+            .arg(files()).arg(hide_muted()).arg(color_by_instrument()).arg(flow())
+            .arg(Arg::with_name("page-height").long("page-height").value_name("PIXELS").help("Splits the song into pages of at most this height")))
+        .subcommand(SubCommand::with_name("pdf").about("Writes the whole song to a PDF document for printing")
+            .arg(files()).arg(hide_muted()).arg(color_by_instrument()).arg(flow())
+            .arg(Arg::with_name("paper").long("paper").value_name("SIZE").possible_values(&["a4", "letter"]).help("Paper size (default: a4)"))
+            .arg(Arg::with_name("landscape").long("landscape").help("Lays the pages out in landscape orientation")))
+        .subcommand(SubCommand::with_name("html").about("Writes an HTML page per pattern and an index")
+            .arg(files()))
+        .subcommand(SubCommand::with_name("matrix").about("Renders an overview of the pattern sequence")
+            .arg(files()))
+        .subcommand(SubCommand::with_name("piano-roll").about("Renders the notes of each pattern on a piano roll")
+            .arg(files()).arg(patterns()).arg(color_by_instrument())
+            .arg(Arg::with_name("song").long("song").help("Renders the whole sequence into a single image")))
+        .subcommand(SubCommand::with_name("thumbnails").about("Renders a small overview of each pattern")
+            .arg(files()).arg(patterns())
+            .arg(Arg::with_name("song").long("song").help("Stacks the thumbnails of the whole sequence into a single image"))
+            .arg(Arg::with_name("size").long("size").value_name("WIDTHxHEIGHT").help("Size of the thumbnails (default: 128x128)")))
+        .subcommand(SubCommand::with_name("contact-sheet").about("Composes all patterns into a single grid image")
+            .arg(files()).arg(color_by_instrument())
+            .arg(Arg::with_name("width").long("width").value_name("PIXELS").help("Width of the image (default: 1600)"))
+            .arg(Arg::with_name("columns").long("columns").value_name("N").help("Number of columns (default: about as many as rows)")))
+        .subcommand(SubCommand::with_name("gif").about("Renders an animated GIF per pattern")
+            .arg(files()).arg(patterns()))
+        .subcommand(SubCommand::with_name("frames").about("Renders the song as it is played into numbered frames")
+            .arg(Arg::with_name("FILE").help("Renoise song (.xrns)").required(true))
+            .arg(Arg::with_name("fps").long("fps").value_name("N").help("Frames per second (default: 60)"))
+            .arg(Arg::with_name("stdout").long("stdout").help("Writes the frames to stdout as PNG images")))
+        .subcommand(SubCommand::with_name("diff").about("Shows what changed between two versions of a song")
+            .arg(Arg::with_name("OLD").help("Old version of the song").required(true))
+            .arg(Arg::with_name("NEW").help("New version of the song").required(true))
+            .arg(patterns()))
+        .subcommand(SubCommand::with_name("info").about("Prints the tempo, tracks, patterns and sequence of a song")
+            .arg(files())
+            .arg(Arg::with_name("json").long("json").help("Prints a JSON object per song, on a line of its own")))
+        .subcommand(SubCommand::with_name("dump").about("Prints the patterns of a song as text")
+            .arg(files()).arg(patterns())
+            .arg(Arg::with_name("tracks").long("tracks").value_name("LIST").help("Only these tracks, by index, range or name, e.g. 0-1,Drum*")))
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Failure> {
+    let (command, args) = match matches.subcommand() {
+        (command, Some(args)) => (command, args),
+        _ => return Err(Failure::Usage(String::from("no command given")))
+    };
+
+    let output_dir = PathBuf::from(args.value_of("output-dir").unwrap_or("."));
+    let jobs: usize = parse_option(args, "jobs")?.unwrap_or(0);
+    let options = OutputOptions {
+        format: args.value_of("format").and_then(export::Format::from_name).unwrap_or(export::Format::Png),
+        template: match args.value_of("name-template") {
+            Some(template) => Some(NameTemplate::parse(template).map_err(|e| Failure::Usage(e.to_string()))?),
+            None => None
+        },
+        written: std::sync::Mutex::new(std::collections::HashMap::new())
+    };
+
+    match command {
+        "diff" => return run_diff(args, &output_dir, &options, jobs),
+        "frames" => return run_frames(args, &output_dir, &options),
+        "render" if args.is_present("watch") => return run_watch(args, &output_dir, &options, jobs),
+        _ => {}
+    }
+
+    let arguments: Vec<&str> = args.values_of("FILE").map(|f| f.collect()).unwrap_or_default();
+    let mut songs: Vec<(String, Result<SongFile, Failure>)> = vec![];
+    for &argument in arguments.iter() {
+        match find_songs(argument) {
+            Ok(found) => songs.extend(found.into_iter().map(|song_file| (song_file.path.display().to_string(), Ok(song_file)))),
+            Err(failure) => songs.push((argument.to_string(), Err(failure)))
+        }
+    }
+    // a single song named directly is the only one to leave its output in the
+    // output directory itself
+    if songs.len() == 1 && arguments == [songs[0].0.as_str()] {
+        let (filename, song_file) = songs.remove(0);
+        song_file?;
+        return process_song(command, args, &filename, output_dir, &options, jobs);
+    }
+
+    // with several songs, the output of each goes into a directory of its own,
+    // unless a name template says where it goes, and failing songs are
+    // reported and skipped; songs whose output would overwrite that of another
+    // song fail too
+    let mut directories: std::collections::HashMap<PathBuf, String> = std::collections::HashMap::new();
+    let mut results: Vec<(String, Result<(), Failure>)> = vec![];
+    for (filename, song_file) in songs {
+        let result = song_file.and_then(|song_file| {
+            let directory = if options.template.is_some() { output_dir.clone() } else { output_dir.join(&song_file.output_name) };
+            if options.template.is_none() {
+                if let Some(other) = directories.insert(directory.clone(), filename.clone()) {
+                    return Err(Failure::Output(format!("the output of {} would go into {} like that of {}", filename, directory.display(), other)));
+                }
+            }
+
+            // a panic is a bug, but should not cost the songs after it
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| process_song(command, args, &filename, directory, &options, jobs)))
+                .unwrap_or_else(|panic| Err(Failure::Output(format!("processing {} failed: {}", filename, panic_message(&*panic)))))
+        });
+        if let Err(failure) = &result {
+            eprintln!("error: {}", failure);
+        }
+        results.push((filename, result));
+    }
+
+    // the summary stays out of the way of songs printed to stdout
+    if command == "info" || command == "dump" {
+        print_summary(&results, &mut std::io::stderr())?;
+    } else {
+        ignore_broken_pipe(print_summary(&results, &mut std::io::stdout().lock()))?;
+    }
+
+    // the exit code tells what went wrong with the first failing song
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+    let message = format!("{} of {} songs failed", failed, results.len());
+    match results.into_iter().find_map(|(_, result)| result.err()) {
+        Some(Failure::Usage(_)) => Err(Failure::Usage(message)),
+        Some(Failure::Input(_)) => Err(Failure::Input(message)),
+        Some(Failure::Output(_)) => Err(Failure::Output(message)),
+        None => Ok(())
+    }
+}
+
+// Runs a command on a song, writing the output into `directory`.
+fn process_song(command: &str, args: &ArgMatches, filename: &str, directory: PathBuf, options: &OutputOptions, jobs: usize) -> Result<(), Failure> {
+    // the commands printing the song keep their output free of status
+    // messages
+    let text_output = command == "info" || command == "dump";
+    let song = if text_output { read_song(filename)? } else { load(filename)? };
+
+    match command {
+        "info" if args.is_present("json") => ignore_broken_pipe(print_info_json(filename, &song, &mut std::io::stdout().lock())),
+        "info" => ignore_broken_pipe(print_info(&song, &mut std::io::stdout().lock())),
+        "dump" => {
+            let (tracks, patterns) = (selected_tracks(&song, args)?, selected_patterns(&song, args)?);
+            ignore_broken_pipe(dump(&song, &tracks, &patterns, &mut std::io::stdout().lock()))
+        },
+        _ => {
+            let output = Output::new(directory, options, &song);
+            match command {
+                "render" => render_patterns(&song, args, &output, jobs, &selected_patterns(&song, args)?),
+                "sequence" => render_sequence(&song, args, &output, jobs),
+                "song" => render_song(&song, args, &output, jobs),
+                "pdf" => render_pdf(&song, args, &output),
+                "html" => render_html(&song, &output, jobs),
+                "matrix" => render_matrix(&song, &output),
+                "piano-roll" => render_piano_roll(&song, args, &output, jobs),
+                "thumbnails" => render_thumbnails(&song, args, &output, jobs),
+                "contact-sheet" => render_contact_sheet(&song, args, &output),
+                "gif" => render_gifs(&song, args, &output, jobs),
+                _ => Err(Failure::Usage(format!("unknown command {}", command)))
+            }
+        }
+    }
+}
+
+// A song to process, and the directory its output goes into when there are
+// several songs: its path below the directory or glob pattern it was found
+// through without the extension, or just its name if it was given directly.
+struct SongFile {
+    path: PathBuf,
+    output_name: PathBuf
+}
+
+const GLOB_CHARACTERS: [char; 3] = ['*', '?', '['];
 
-    if args.len() < 2 {
-        println!("usage: {} FILENAME", args[0]);
-        return;
+// Finds the songs an argument names: the song itself, the songs (`.xrns`
+// files) anywhere below a directory, or those matching a glob pattern such as
+// `archive/**/demo?.xrns`, looking into matching directories as well.
+fn find_songs(argument: &str) -> Result<Vec<SongFile>, Failure> {
+    let path = Path::new(argument);
+    if path.is_dir() {
+        let songs = songs_below(path, path).map_err(|e| Failure::Input(format!("cannot read {}: {}", argument, e)))?;
+        if songs.is_empty() {
+            return Err(Failure::Input(format!("no songs in {}", argument)));
+        }
+        return Ok(songs);
+    }
+    if path.exists() || !argument.contains(GLOB_CHARACTERS) {
+        let output_name = PathBuf::from(path.file_stem().unwrap_or_default());
+        return Ok(vec![SongFile { path: path.to_path_buf(), output_name }]);
+    }
+
+    let matches = glob::glob(argument).map_err(|e| Failure::Usage(format!("invalid pattern {}: {}", argument, e)))?;
+
+    // the output is named after the path below the components before the
+    // first wildcard
+    let base: PathBuf = path.iter().take_while(|c| !c.to_string_lossy().contains(GLOB_CHARACTERS)).collect();
+    let mut songs: Vec<SongFile> = vec![];
+    for song in matches {
+        let song = song.map_err(|e| Failure::Input(format!("cannot read {}: {}", e.path().display(), e.error())))?;
+        if song.is_dir() {
+            songs.extend(songs_below(&base, &song).map_err(|e| Failure::Input(format!("cannot read {}: {}", song.display(), e)))?);
+        } else if song.is_file() {
+            songs.push(SongFile { output_name: song_output_name(&base, &song), path: song });
+        }
+    }
+    if songs.is_empty() {
+        return Err(Failure::Input(format!("no songs match {}", argument)));
     }
 
-    let filename = std::path::Path::new(&args[1]);
-    println!("Reading {}", filename.display());
-    let song = renoise::Song::from_xrns(&filename).unwrap();
+    Ok(songs)
+}
+
+// Finds the songs anywhere below a directory, in order, naming their output
+// directories after their paths below `base`.
+fn songs_below(base: &Path, directory: &Path) -> std::io::Result<Vec<SongFile>> {
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(directory)?.map(|entry| entry.map(|entry| entry.path())).collect::<std::io::Result<_>>()?;
+    entries.sort();
+
+    let mut songs: Vec<SongFile> = vec![];
+    for entry in entries {
+        if entry.is_dir() {
+            songs.extend(songs_below(base, &entry)?);
+        } else if entry.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("xrns")) {
+            songs.push(SongFile { output_name: song_output_name(base, &entry), path: entry });
+        }
+    }
 
+    Ok(songs)
+}
+
+fn song_output_name(base: &Path, song: &Path) -> PathBuf {
+    song.strip_prefix(base).unwrap_or(song).with_extension("")
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => String::from("panic")
+    }
+}
+
+// Prints a table of the songs that were processed and why those that failed
+// did.
+fn print_summary<W: Write>(results: &[(String, Result<(), Failure>)], out: &mut W) -> std::io::Result<()> {
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+    let width = results.iter().filter(|(_, result)| result.is_err()).map(|(filename, _)| filename.chars().count()).max().unwrap_or(0);
+
+    writeln!(out)?;
+    writeln!(out, "{}, {} succeeded, {} failed", plural(results.len() as u32, "song"), results.len() - failed, failed)?;
+    for (filename, result) in results.iter() {
+        match result {
+            Ok(()) => writeln!(out, "  ok     {}", filename)?,
+            Err(failure) => writeln!(out, "  error  {:<width$}  {}", filename, failure, width = width)?
+        }
+    }
+
+    Ok(())
+}
+
+fn read_song(filename: &str) -> Result<renoise::Song, Failure> {
+    renoise::Song::from_xrns(Path::new(filename)).map_err(|e| Failure::Input(format!("cannot read {}: {}", filename, e)))
+}
+
+// Reads a song, telling what is going on.
+fn load(filename: &str) -> Result<renoise::Song, Failure> {
+    println!("Reading {}", filename);
+    let song = read_song(filename)?;
     println!("Loaded song {} by {}", song.global_song_data.song_name, song.global_song_data.artist);
 
+    Ok(song)
+}
+
+// Treats the reader of the output going away (e.g. `patren dump | head`) as
+// success rather than an error.
+fn ignore_broken_pipe(result: std::io::Result<()>) -> Result<(), Failure> {
+    match result {
+        Err(error) if error.kind() != std::io::ErrorKind::BrokenPipe => Err(error.into()),
+        _ => Ok(())
+    }
+}
+
+fn render_patterns(song: &renoise::Song, args: &ArgMatches, output: &Output, jobs: usize, patterns: &[usize]) -> Result<(), Failure> {
+    let tracks = match args.value_of("tracks") {
+        Some(_) => Some(selected_tracks(song, args)?),
+        None => None
+    };
+    let lines = match args.value_of("lines") {
+        Some(range) => Some(parse_line_range(range).ok_or_else(|| Failure::Usage(format!("invalid line range {}", range)))?),
+        None => None
+    };
+    let note_colors = note_colors(args);
+
     println!("Rendering images");
-    for i in 0..song.pattern_pool.patterns.len() {
-        println!("pattern {:02}", i);
-        let image = renderer::render(&song, i);
-        println!("writing file...");
-        image.save(format!("pattern{:02}.png", i)).unwrap();
+    run_jobs(jobs, patterns, |i| {
+        let image = if tracks.is_some() || lines.is_some() {
+            let tracks = tracks.clone().unwrap_or_else(|| (0..song.tracks.len()).collect());
+            let number_of_lines = song.pattern_pool.patterns[i].number_of_lines;
+            let lines = lines.clone().unwrap_or(0..number_of_lines);
+            if lines.start >= number_of_lines || (lines.end != u32::MAX && lines.end > number_of_lines) {
+                return Err(Failure::Usage(format!("pattern {:02} has only lines 0-{}", i, number_of_lines.saturating_sub(1))));
+            }
+            renderer::render_region(song, i, &tracks, lines, note_colors)
+        } else {
+            renderer::render(song, i, note_colors)
+        };
+        Ok(format!("pattern {:02}, {}", i, output.save(&image, "pattern{pattern}.{ext}", Subject::pattern(i), &pattern_metadata(song, i))?))
+    })
+}
+
+fn render_sequence(song: &renoise::Song, args: &ArgMatches, output: &Output, jobs: usize) -> Result<(), Failure> {
+    let sequence_entries: Vec<usize> = (0..song.pattern_sequence.sequence_entries.len()).collect();
+    let (mute_style, note_colors, flows) = (mute_style(args), note_colors(args), flow(args).sequence_flow(song));
+
+    println!("Rendering images per sequence position");
+    run_jobs(jobs, &sequence_entries, |i| {
+        let image = renderer::render_sequence_entry(song, i, mute_style, note_colors, flows.get(i));
+        Ok(format!("sequence position {:03}, {}", i, output.save(&image, "seq{seq}.{ext}", Subject::sequence_index(i), &sequence_entry_metadata(song, i))?))
+    })
+}
+
+fn render_song(song: &renoise::Song, args: &ArgMatches, output: &Output, jobs: usize) -> Result<(), Failure> {
+    let page_height: Option<u32> = parse_option(args, "page-height")?;
+
+    println!("Rendering song");
+    let pages = renderer::render_song(song, page_height, mute_style(args), note_colors(args), flow(args));
+    let page_indices: Vec<usize> = (0..pages.len()).collect();
+    run_jobs(jobs, &page_indices, |i| {
+        if pages.len() == 1 {
+            output.save(&pages[i], "song.{ext}", Subject::default(), &[])
+        } else {
+            Ok(format!("page {:02}, {}", i, output.save(&pages[i], "song{page}.{ext}", Subject::page(i), &[])?))
+        }
+    })
+}
+
+fn render_pdf(song: &renoise::Song, args: &ArgMatches, output: &Output) -> Result<(), Failure> {
+    let paper = match args.value_of("paper") {
+        Some("letter") => print::Paper::Letter,
+        _ => print::Paper::A4
+    };
+    let orientation = if args.is_present("landscape") { print::Orientation::Landscape } else { print::Orientation::Portrait };
+
+    let path = output.path("song.{ext}", Subject::default(), "pdf")?;
+    println!("Rendering {}", path.strip_prefix(&output.directory).unwrap_or(&path).display());
+    let file = output.create(&path)?;
+    print::write_pdf(song, file, paper, orientation, mute_style(args), note_colors(args), flow(args))?
+        .flush()
+        .map_err(|e| Failure::Output(format!("cannot write {}: {}", path.display(), e)))
+}
+
+fn render_html(song: &renoise::Song, output: &Output, jobs: usize) -> Result<(), Failure> {
+    let patterns: Vec<usize> = (0..song.pattern_pool.patterns.len()).collect();
+    let index_path = output.fixed_path("index.html")?;
+    let paths: Vec<PathBuf> = patterns.iter()
+        .map(|&i| output.path("pattern{pattern}.{ext}", Subject::pattern(i), "html"))
+        .collect::<Result<_, _>>()?;
+
+    // the pages link to each other by their paths relative to the index
+    let relative = |path: &Path| -> Vec<String> {
+        path.strip_prefix(&output.directory).unwrap_or(path).iter().map(|c| c.to_string_lossy().into_owned()).collect()
+    };
+
+    println!("Rendering HTML");
+    run_jobs(jobs, &patterns, |i| {
+        let mut index_href = vec![String::from(".."); relative(&paths[i]).len() - 1];
+        index_href.push(String::from("index.html"));
+        output.write(&paths[i], html::render(song, i, &index_href))?;
+        Ok(format!("pattern {:02}", i))
+    })?;
+    let pattern_hrefs: Vec<Vec<String>> = paths.iter().map(|path| relative(path)).collect();
+    output.write(&index_path, html::render_index(song, &pattern_hrefs))
+}
+
+fn render_matrix(song: &renoise::Song, output: &Output) -> Result<(), Failure> {
+    println!("Rendering pattern matrix");
+    println!("{}", output.save(&matrix::render(song), "matrix.{ext}", Subject::default(), &[])?);
+
+    Ok(())
+}
+
+fn render_piano_roll(song: &renoise::Song, args: &ArgMatches, output: &Output, jobs: usize) -> Result<(), Failure> {
+    let color_mode = match note_colors(args) {
+        renderer::NoteColors::Instrument => piano_roll::ColorMode::Instrument,
+        renderer::NoteColors::Default => piano_roll::ColorMode::Track
+    };
+
+    println!("Rendering piano roll");
+    if args.is_present("song") {
+        println!("{}", output.save(&piano_roll::render_song(song, color_mode), "pianoroll.{ext}", Subject::default(), &[])?);
+        return Ok(());
+    }
+
+    run_jobs(jobs, &selected_patterns(song, args)?, |i| {
+        Ok(format!("pattern {:02}, {}", i, output.save(&piano_roll::render(song, i, color_mode), "pianoroll{pattern}.{ext}", Subject::pattern(i), &[])?))
+    })
+}
+
+fn render_thumbnails(song: &renoise::Song, args: &ArgMatches, output: &Output, jobs: usize) -> Result<(), Failure> {
+    let (width, height) = match args.value_of("size") {
+        Some(size) => parse_size(size).ok_or_else(|| Failure::Usage(format!("invalid size {}", size)))?,
+        None => (128, 128)
+    };
+
+    println!("Rendering thumbnails");
+    if args.is_present("song") {
+        println!("{}", output.save(&thumbnail::render_song(song, width, height), "thumbnail.{ext}", Subject::default(), &[])?);
+        return Ok(());
+    }
+
+    run_jobs(jobs, &selected_patterns(song, args)?, |i| {
+        Ok(format!("pattern {:02}, {}", i, output.save(&thumbnail::render(song, i, width, height), "thumbnail{pattern}.{ext}", Subject::pattern(i), &[])?))
+    })
+}
+
+fn render_contact_sheet(song: &renoise::Song, args: &ArgMatches, output: &Output) -> Result<(), Failure> {
+    let width: u32 = parse_option(args, "width")?.unwrap_or(1600);
+    let columns: Option<u32> = parse_option(args, "columns")?;
+
+    println!("Rendering contact sheet");
+    println!("{}", output.save(&contact_sheet::render(song, width, columns, note_colors(args)), "contactsheet.{ext}", Subject::default(), &[])?);
+
+    Ok(())
+}
+
+fn render_gifs(song: &renoise::Song, args: &ArgMatches, output: &Output, jobs: usize) -> Result<(), Failure> {
+    println!("Rendering animations");
+    run_jobs(jobs, &selected_patterns(song, args)?, |i| {
+        animation::render_gif(song, i, output.create(&output.path("pattern{pattern}.{ext}", Subject::pattern(i), "gif")?)?)?;
+        Ok(format!("pattern {:02}", i))
+    })
+}
+
+// Renders the patterns of a song, then keeps watching the song and renders
+// the patterns whose images changed again each time it is saved.  Renoise
+// saves to a temporary file that it renames over the song, so the directory is
+// watched rather than the file, and the song is only read once no events came
+// in for a moment.  Songs that cannot be read, e.g. while they are being
+// written, are reported and skipped.
+fn run_watch(args: &ArgMatches, output_dir: &Path, options: &OutputOptions, jobs: usize) -> Result<(), Failure> {
+    let filenames: Vec<&str> = args.values_of("FILE").map(|f| f.collect()).unwrap_or_default();
+    let filename = match filenames.as_slice() {
+        [filename] => *filename,
+        _ => return Err(Failure::Usage(String::from("--watch takes a single song")))
+    };
+    let path = Path::new(filename);
+    let file_name = path.file_name().ok_or_else(|| Failure::Usage(format!("{} is not a file", filename)))?;
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new(".")
+    };
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(|e| Failure::Input(format!("cannot watch {}: {}", filename, e)))?;
+    notify::Watcher::watch(&mut watcher, directory, notify::RecursiveMode::NonRecursive)
+        .map_err(|e| Failure::Input(format!("cannot watch {}: {}", directory.display(), e)))?;
+
+    let mut song = load(filename)?;
+    render_patterns(&song, args, &Output::new(output_dir.to_path_buf(), options, &song), jobs, &selected_patterns(&song, args)?)?;
+
+    loop {
+        println!("Watching {} for changes", filename);
+        loop {
+            let event = receiver.recv().map_err(|e| Failure::Input(format!("cannot watch {}: {}", filename, e)))?;
+            if is_song_saved(event, file_name) {
+                break;
+            }
+        }
+        while receiver.recv_timeout(WATCH_SETTLE_TIME).is_ok() {}
+
+        let new = match load(filename) {
+            Ok(new) => new,
+            Err(failure) => {
+                eprintln!("error: {}", failure);
+                continue;
+            }
+        };
+        // the song is kept as it was to try again after the next save
+        match render_changed_patterns(&song, &new, args, output_dir, options, jobs) {
+            Ok(()) => song = new,
+            Err(failure) => eprintln!("error: {}", failure)
+        }
+    }
+}
+
+// Renders the images of the patterns that look different in the `new` song,
+// or are named differently, such as when the number of patterns grows past
+// 100, and deletes the images of patterns that are gone.
+fn render_changed_patterns(old: &renoise::Song, new: &renoise::Song, args: &ArgMatches, output_dir: &Path, options: &OutputOptions, jobs: usize) -> Result<(), Failure> {
+    let (old_output, new_output) = (Output::new(output_dir.to_path_buf(), options, old), Output::new(output_dir.to_path_buf(), options, new));
+    let image_paths = |output: &Output, patterns: Vec<usize>| -> Result<Vec<(usize, PathBuf)>, Failure> {
+        patterns.into_iter().map(|i| Ok((i, output.name("pattern{pattern}.{ext}", Subject::pattern(i), options.format.extension())?))).collect()
+    };
+    let old_paths = image_paths(&old_output, selected_patterns(old, args)?)?;
+    let new_paths = image_paths(&new_output, selected_patterns(new, args)?)?;
+
+    // the metadata of the song is written into every image
+    let song_changed = export::song_metadata(old) != export::song_metadata(new);
+    let patterns: Vec<usize> = new_paths.iter()
+        .filter(|&(i, path)| song_changed || !old_paths.contains(&(*i, path.clone()))
+            || diff::pattern_image_changed(old, new, *i) || pattern_metadata(old, *i) != pattern_metadata(new, *i))
+        .map(|&(i, _)| i)
+        .collect();
+    let removed: Vec<&PathBuf> = old_paths.iter().map(|(_, path)| path).filter(|&path| new_paths.iter().all(|(_, p)| p != path)).collect();
+
+    if patterns.is_empty() && removed.is_empty() {
+        println!("No pattern changed");
+        return Ok(());
+    }
+    for path in removed {
+        match std::fs::remove_file(path) {
+            Ok(()) => println!("Deleted {}", path.display()),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => return Err(Failure::Output(format!("cannot delete {}: {}", path.display(), e)))
+        }
+    }
+    // files are written again on purpose
+    options.forget_written();
+    render_patterns(new, args, &new_output, jobs, &patterns)
+}
+
+// Tells whether a file system event wrote, created or renamed the song.
+// Reading the song does not count, or watching would never settle.
+fn is_song_saved(event: notify::Result<notify::Event>, file_name: &std::ffi::OsStr) -> bool {
+    let event = match event {
+        Ok(event) => event,
+        Err(_) => return false
+    };
+
+    match event.kind {
+        notify::EventKind::Create(_) | notify::EventKind::Modify(notify::event::ModifyKind::Data(_))
+            | notify::EventKind::Modify(notify::event::ModifyKind::Name(_)) | notify::EventKind::Modify(notify::event::ModifyKind::Any) => {
+            event.paths.iter().any(|path| path.file_name() == Some(file_name))
+        },
+        _ => false
+    }
+}
+
+// Renders the song as it is played into numbered frames in the output
+// directory, or to stdout, in which case stdout is kept free of status
+// messages.
+fn run_frames(args: &ArgMatches, output_dir: &Path, options: &OutputOptions) -> Result<(), Failure> {
+    let filename = args.value_of("FILE").unwrap_or_default();
+    let frames_per_second: u32 = parse_option(args, "fps")?.unwrap_or(60);
+    if frames_per_second == 0 {
+        return Err(Failure::Usage(String::from("--fps must be at least 1")));
+    }
+
+    if args.is_present("stdout") {
+        let song = read_song(filename)?;
+
+        eprintln!("Rendering frames to stdout");
+        let stdout = std::io::stdout();
+        let mut out = std::io::BufWriter::new(stdout.lock());
+        animation::render_frames(&song, frames_per_second, |_, image| {
+            image::png::PNGEncoder::new(&mut out).encode(image, image.width(), image.height(), image::ColorType::RGBA(8))?;
+            Ok(())
+        })?;
+        return Ok(());
+    }
+
+    let song = load(filename)?;
+    let output = Output::new(output_dir.to_path_buf(), options, &song);
+
+    println!("Rendering frames to {}", output_dir.display());
+    animation::render_frames(&song, frames_per_second, |index, image| {
+        if index % frames_per_second as usize == 0 {
+            println!("second {}", index / frames_per_second as usize);
+        }
+        let path = output.fixed_path(&format!("frame{:06}.{}", index, options.format.extension())).map_err(|failure| failure.to_string())?;
+        output.save_as(image, &path, &[]).map_err(|failure| failure.to_string())?;
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+// Compares two versions of a song: prints the changes to its structure, and
+// renders `diffNN.png` for each pattern with changed cells, highlighting
+// added, removed and modified cells.
+fn run_diff(args: &ArgMatches, output_dir: &Path, options: &OutputOptions, jobs: usize) -> Result<(), Failure> {
+    let (old_filename, new_filename) = (args.value_of("OLD").unwrap_or_default(), args.value_of("NEW").unwrap_or_default());
+
+    println!("Comparing {} to {}", old_filename, new_filename);
+    let old = read_song(old_filename)?;
+    let new = read_song(new_filename)?;
+
+    let summary = diff::summary(&old, &new);
+    if summary.is_empty() {
+        println!("Same tracks, patterns and pattern sequence");
+    }
+    for change in summary.iter() {
+        println!("{}", change);
+    }
+
+    let output = Output::new(output_dir.to_path_buf(), options, &new);
+    let tracks = diff::match_tracks(&old, &new);
+    let patterns = diff::match_patterns(&old, &new, &tracks);
+    println!("Rendering changed patterns");
+    run_jobs(jobs, &selected_patterns(&new, args)?, |i| {
+        let changes = diff::pattern_changes(&old, &new, i, &tracks, &patterns);
+        if changes.is_empty() {
+            return Ok(format!("pattern {:02}, unchanged", i));
+        }
+
+        let count = |change: diff::Change| changes.iter().filter(|c| c.change == change).count();
+        Ok(format!("pattern {:02}, {} cells added, {} removed, {} modified, {}", i,
+            count(diff::Change::Added), count(diff::Change::Removed), count(diff::Change::Modified),
+            output.save(&diff::render(&new, i, &changes), "diff{pattern}.{ext}", Subject::pattern(i), &pattern_metadata(&new, i))?))
+    })
+}
+
+fn print_info<W: Write>(song: &renoise::Song, out: &mut W) -> std::io::Result<()> {
+    let song_data = &song.global_song_data;
+    writeln!(out, "Title:    {}", song_data.song_name)?;
+    writeln!(out, "Artist:   {}", song_data.artist)?;
+    writeln!(out, "Tempo:    {} BPM, {} lines per beat, {} ticks per line", song_data.beats_per_min, song_data.lines_per_beat, song_data.ticks_per_line)?;
+    let tenths = (duration(song) * 10.0).round() as u64;
+    writeln!(out, "Duration: {}:{:02}.{}", tenths / 600, tenths % 600 / 10, tenths % 10)?;
+
+    writeln!(out, "Tracks:   {}", song.tracks.len())?;
+    for (index, track) in song.tracks.iter().enumerate() {
+        let mut columns = vec![plural(track.number_of_visible_note_columns, "note column")];
+        for &(visible, name) in [(track.volume_column_is_visible, "volume"), (track.panning_column_is_visible, "panning"), (track.delay_column_is_visible, "delay")].iter() {
+            if visible {
+                columns.push(String::from(name));
+            }
+        }
+        columns.push(plural(track.number_of_visible_effect_columns, "effect column"));
+        writeln!(out, "  {:02} {:<9} {} ({})", index, track_type(track), track.name, columns.join(", "))?;
+    }
+
+    writeln!(out, "Patterns: {}", song.pattern_pool.patterns.len())?;
+    for (index, pattern) in song.pattern_pool.patterns.iter().enumerate() {
+        writeln!(out, "{}", format!("  {:02} {:3} lines {}", index, pattern.number_of_lines, pattern.name).trim_end())?;
+        for (track, alias) in aliases(pattern) {
+            writeln!(out, "        track {:02} is an alias of pattern {:02}", track, alias)?;
+        }
+    }
+
+    writeln!(out, "Sequence: {}", song.pattern_sequence.sequence_entries.len())?;
+    for (index, entry) in song.pattern_sequence.sequence_entries.iter().enumerate() {
+        writeln!(out, "{}", format!("  {:03} pattern {:02} {}", index, entry.pattern, entry.section_name).trim_end())?;
+    }
+
+    Ok(())
+}
+
+// Prints what `print_info` does as a single line of JSON, for scripts.
+fn print_info_json<W: Write>(filename: &str, song: &renoise::Song, out: &mut W) -> std::io::Result<()> {
+    let song_data = &song.global_song_data;
+
+    let tracks: Vec<String> = song.tracks.iter().enumerate().map(|(index, track)| format!(
+        "{{\"index\":{},\"name\":{},\"type\":{},\"note_columns\":{},\"effect_columns\":{},\"volume_column\":{},\"panning_column\":{},\"delay_column\":{}}}",
+        index, json_string(&track.name), json_string(track_type(track)), track.number_of_visible_note_columns, track.number_of_visible_effect_columns,
+        track.volume_column_is_visible, track.panning_column_is_visible, track.delay_column_is_visible
+    )).collect();
+
+    let patterns: Vec<String> = song.pattern_pool.patterns.iter().enumerate().map(|(index, pattern)| {
+        let aliases: Vec<String> = aliases(pattern).map(|(track, alias)| format!("{{\"track\":{},\"pattern\":{}}}", track, alias)).collect();
+        format!("{{\"index\":{},\"name\":{},\"lines\":{},\"aliases\":[{}]}}", index, json_string(&pattern.name), pattern.number_of_lines, aliases.join(","))
+    }).collect();
+
+    let sequence: Vec<String> = song.pattern_sequence.sequence_entries.iter().enumerate().map(|(index, entry)| format!(
+        "{{\"index\":{},\"pattern\":{},\"section\":{}}}", index, entry.pattern, json_string(&entry.section_name)
+    )).collect();
+
+    writeln!(out, "{{\"file\":{},\"title\":{},\"artist\":{},\"beats_per_min\":{},\"lines_per_beat\":{},\"ticks_per_line\":{},\"duration\":{:.3},\"tracks\":[{}],\"patterns\":[{}],\"sequence\":[{}]}}",
+        json_string(filename), json_string(&song_data.song_name), json_string(&song_data.artist),
+        song_data.beats_per_min, song_data.lines_per_beat, song_data.ticks_per_line, duration(song),
+        tracks.join(","), patterns.join(","), sequence.join(","))
+}
+
+// How long the song plays in seconds, following its tempo changes, pattern
+// breaks and delays.
+fn duration(song: &renoise::Song) -> f64 {
+    animation::timeline(song).last().map_or(0.0, |l| l.start + l.duration) / 1000.0
+}
+
+fn track_type(track: &renoise::Track) -> &'static str {
+    match track.r#type.as_str() {
+        "SequencerGroupTrack" => "group",
+        "SequencerSendTrack" => "send",
+        "SequencerMasterTrack" => "master",
+        _ => "track"
+    }
+}
+
+// The tracks of a pattern that are aliases, and the patterns they point to.
+fn aliases(pattern: &renoise::Pattern) -> impl Iterator<Item = (usize, i32)> + '_ {
+    pattern.tracks.iter().enumerate()
+        .filter(|(_, track)| track.alias_pattern_index >= 0)
+        .map(|(index, track)| (index, track.alias_pattern_index))
+}
+
+fn plural(count: u32, noun: &str) -> String {
+    format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c)
+        }
+    }
+    json.push('"');
+    json
+}
+
+// Prints patterns as text, a row per line with the tracks separated by bars,
+// and dots for what is empty, like in the rendered images.
+fn dump<W: Write>(song: &renoise::Song, tracks: &[usize], patterns: &[usize], out: &mut W) -> std::io::Result<()> {
+    let layout = layout::Layout::new(song, tracks);
+
+    for &pattern in patterns.iter() {
+        let pat = &song.pattern_pool.patterns[pattern];
+        writeln!(out, "{}", format!("Pattern {:02} {}", pattern, pat.name).trim_end())?;
+
+        let track_lines: Vec<&[renoise::Line]> = layout.tracks.iter().map(|t| song.pattern_track_lines(pattern, t.index)).collect();
+        for line_index in 0..pat.number_of_lines {
+            let cells: Vec<String> = layout.tracks.iter().zip(track_lines.iter()).map(|(track_layout, lines)| {
+                let line = lines.iter().find(|l| l.index == line_index);
+                let columns: Vec<String> = track_layout.columns.iter().map(|column| {
+                    let note_column = line.and_then(|l| l.note_columns.get(column.index)).and_then(|n| n.as_ref());
+                    let effect_column = line.and_then(|l| l.effect_columns.get(column.index)).and_then(|e| e.as_ref());
+                    let text = match (column.kind, note_column, effect_column) {
+                        (layout::ColumnKind::Note, Some(note), _) => format!("{}{}", note.note, note.instrument),
+                        (layout::ColumnKind::Note, None, _) => String::from("....."),
+                        (layout::ColumnKind::Volume, note, _) => note.map_or("..", |n| &n.volume).to_string(),
+                        (layout::ColumnKind::Panning, note, _) => note.map_or("..", |n| &n.panning).to_string(),
+                        (layout::ColumnKind::Effect, _, Some(effect)) => format!("{}{}", effect.number, effect.value),
+                        (layout::ColumnKind::Effect, _, None) => String::from("....")
+                    };
+                    text.replace(' ', ".")
+                }).collect();
+                columns.join(" ")
+            }).collect();
+            writeln!(out, "{:03} | {}", line_index, cells.join(" | "))?;
+        }
+    }
+
+    Ok(())
+}
+
+// How files are saved, as given on the command line: images in which format,
+// and named after which template.  Remembers the files written so far and
+// which song they show, so that songs and images cannot overwrite each other
+// when the template does not tell them apart.
+struct OutputOptions {
+    format: export::Format,
+    template: Option<NameTemplate>,
+    written: std::sync::Mutex<std::collections::HashMap<PathBuf, String>>
+}
+
+impl OutputOptions {
+    fn forget_written(&self) {
+        self.written.lock().unwrap_or_else(std::sync::PoisonError::into_inner).clear();
+    }
+}
+
+// Where files showing a song are saved, and with the metadata of the song.
+struct Output<'a> {
+    directory: PathBuf,
+    format: export::Format,
+    template: Option<&'a NameTemplate>,
+    written: &'a std::sync::Mutex<std::collections::HashMap<PathBuf, String>>,
+    song: &'a renoise::Song,
+    metadata: Vec<(&'static str, String)>
+}
+
+impl<'a> Output<'a> {
+    // The directory is only created when the first file is written to it, so
+    // that songs failing before leave nothing behind.
+    fn new(directory: PathBuf, options: &'a OutputOptions, song: &'a renoise::Song) -> Output<'a> {
+        Output {
+            directory,
+            format: options.format,
+            template: options.template.as_ref(),
+            written: &options.written,
+            song,
+            metadata: export::song_metadata(song)
+        }
+    }
+
+    // Where to write a file showing `subject`: named after the template given
+    // by the user, or `default_template` if none was.  Creates the directories
+    // the template asks for, and fails if another file was written there
+    // before.
+    fn path(&self, default_template: &str, subject: Subject, extension: &str) -> Result<PathBuf, Failure> {
+        self.claim(self.name(default_template, subject, extension)?)
+    }
+
+    // Where a file showing `subject` is written, without claiming it.
+    fn name(&self, default_template: &str, subject: Subject, extension: &str) -> Result<PathBuf, Failure> {
+        let name = match self.template {
+            Some(template) => template.expand(self.song, &subject, extension)?,
+            None => NameTemplate::parse(default_template)?.expand(self.song, &subject, extension)?
+        };
+        Ok(self.directory.join(name))
+    }
+
+    // Where to write a file whose name does not follow the template.
+    fn fixed_path(&self, file_name: &str) -> Result<PathBuf, Failure> {
+        self.claim(self.directory.join(file_name))
+    }
+
+    fn claim(&self, path: PathBuf) -> Result<PathBuf, Failure> {
+        let song_name = self.song.global_song_data.song_name.clone();
+        if let Some(other) = self.written.lock().unwrap_or_else(std::sync::PoisonError::into_inner).insert(path.clone(), song_name) {
+            return Err(Failure::Output(format!("{} was written before for song {}; the name template needs placeholders telling the files apart", path.display(), other)));
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| Failure::Output(format!("cannot create {}: {}", parent.display(), e)))?;
+        }
+
+        Ok(path)
+    }
+
+    // Saves an image showing `subject` in the format, adding `metadata` to that
+    // of the song, and returns a short description of it for the progress
+    // output.
+    fn save(&self, image: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, default_template: &str, subject: Subject, metadata: &[(&'static str, String)]) -> Result<String, Failure> {
+        let path = self.path(default_template, subject, self.format.extension())?;
+        self.save_as(image, &path, metadata)
+    }
+
+    fn save_as(&self, image: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, path: &Path, metadata: &[(&'static str, String)]) -> Result<String, Failure> {
+        let metadata: Vec<(&'static str, String)> = self.metadata.iter().chain(metadata.iter()).cloned().collect();
+        export::save(image, self.format, &metadata, path).map_err(|e| Failure::Output(format!("cannot write {}: {}", path.display(), e)))?;
+
+        Ok(format!("image size: {}x{}", image.width(), image.height()))
+    }
+
+    fn write<C: AsRef<[u8]>>(&self, path: &Path, contents: C) -> Result<(), Failure> {
+        std::fs::write(path, contents).map_err(|e| Failure::Output(format!("cannot write {}: {}", path.display(), e)))
+    }
+
+    fn create(&self, path: &Path) -> Result<std::io::BufWriter<std::fs::File>, Failure> {
+        let file = std::fs::File::create(path).map_err(|e| Failure::Output(format!("cannot create {}: {}", path.display(), e)))?;
+
+        Ok(std::io::BufWriter::new(file))
+    }
+}
+
+// Describes a pattern and where it is played in the sequence.
+fn pattern_metadata(song: &renoise::Song, pattern: usize) -> Vec<(&'static str, String)> {
+    let positions: Vec<String> = song.pattern_sequence.sequence_entries.iter().enumerate()
+        .filter(|(_, entry)| entry.pattern as usize == pattern)
+        .map(|(index, _)| format!("{:03}", index))
+        .collect();
+
+    vec![
+        ("Pattern", format!("{:02}", pattern)),
+        ("Sequence positions", positions.join(", "))
+    ]
+}
+
+fn sequence_entry_metadata(song: &renoise::Song, sequence_index: usize) -> Vec<(&'static str, String)> {
+    vec![
+        ("Sequence position", format!("{:03}", sequence_index)),
+        ("Pattern", format!("{:02}", song.pattern_sequence.sequence_entries[sequence_index].pattern))
+    ]
+}
+
+// Runs `job` for each of the `items` on `jobs` threads (or one per CPU core if
+// `jobs` is 0), and prints the progress messages the jobs return in the order
+// of the items, so the output is the same no matter which job finishes first.
+// All jobs run even if some fail or panic; the first failure is returned.
+fn run_jobs<F>(jobs: usize, items: &[usize], job: F) -> Result<(), Failure>
+where
+    F: Fn(usize) -> Result<String, Failure> + Sync
+{
+    use rayon::prelude::*;
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build().map_err(|e| Failure::Output(e.to_string()))?;
+    let (sender, receiver) = std::sync::mpsc::channel::<(usize, Result<String, Failure>)>();
+    let mut first_failure: Option<Failure> = None;
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            pool.install(|| {
+                items.par_iter().enumerate().for_each_with(sender, |sender, (position, &item)| {
+                    // a panic fails the job with its message, which would
+                    // otherwise be lost when the scope rethrows it
+                    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| job(item)))
+                        .unwrap_or_else(|panic| Err(Failure::Output(format!("panicked: {}", panic_message(&*panic)))));
+                    sender.send((position, result)).unwrap();
+                });
+            });
+        });
+
+        // messages of jobs that finished before the ones in front of them
+        let mut finished: std::collections::BTreeMap<usize, Result<String, Failure>> = std::collections::BTreeMap::new();
+        let mut next: usize = 0;
+        for (position, result) in receiver {
+            finished.insert(position, result);
+            while let Some(result) = finished.remove(&next) {
+                match result {
+                    Ok(message) => println!("{}", message),
+                    Err(failure) => { first_failure.get_or_insert(failure); }
+                }
+                next += 1;
+            }
+        }
+    });
+
+    match first_failure {
+        Some(failure) => Err(failure),
+        None => Ok(())
+    }
+}
+
+fn note_colors(args: &ArgMatches) -> renderer::NoteColors {
+    if args.is_present("color-by-instrument") { renderer::NoteColors::Instrument } else { renderer::NoteColors::Default }
+}
+
+fn mute_style(args: &ArgMatches) -> renderer::MuteStyle {
+    if args.is_present("hide-muted") { renderer::MuteStyle::Hide } else { renderer::MuteStyle::Dim }
+}
+
+fn flow(args: &ArgMatches) -> renderer::Flow {
+    if args.is_present("flow") { renderer::Flow::Annotated } else { renderer::Flow::Plain }
+}
+
+// Parses the value of an option, if given.
+fn parse_option<T: std::str::FromStr>(args: &ArgMatches, name: &str) -> Result<Option<T>, Failure> {
+    match args.value_of(name) {
+        Some(value) => value.parse().map(Some).map_err(|_| Failure::Usage(format!("invalid value for --{}: {}", name, value))),
+        None => Ok(None)
+    }
+}
+
+// Returns the tracks selected with `--tracks`, or all of them.
+fn selected_tracks(song: &renoise::Song, args: &ArgMatches) -> Result<Vec<usize>, Failure> {
+    match args.value_of("tracks") {
+        Some(selection) => {
+            let track_names: Vec<&str> = song.tracks.iter().map(|track| track.name.as_str()).collect();
+            select_tracks(&track_names, selection)
+        },
+        None => Ok((0..song.tracks.len()).collect())
+    }
+}
+
+// Returns the patterns selected with `--patterns`, or all of them.
+fn selected_patterns(song: &renoise::Song, args: &ArgMatches) -> Result<Vec<usize>, Failure> {
+    let number_of_patterns = song.pattern_pool.patterns.len();
+
+    match args.value_of("patterns") {
+        Some(list) => parse_index_list(list, number_of_patterns)
+            .ok_or_else(|| Failure::Usage(format!("invalid pattern list {} (the song has {} patterns)", list, number_of_patterns))),
+        None => Ok((0..number_of_patterns).collect())
+    }
+}
+
+// Parses a comma separated list of indices (e.g. `8`) and index ranges (e.g.
+// `0-5`), all of them below `count`.
+fn parse_index_list(list: &str, count: usize) -> Option<Vec<usize>> {
+    let mut indices: Vec<usize> = vec![];
+
+    for part in list.split(',').map(|p| p.trim()) {
+        let (first, last) = match parse_index_range(part) {
+            Some((first, last)) if first <= last && last < count => (first, last),
+            _ => return None
+        };
+
+        for index in first..=last {
+            if !indices.contains(&index) {
+                indices.push(index);
+            }
+        }
+    }
+
+    Some(indices)
+}
+
+// Parses an index (e.g. `8`) or an inclusive index range (e.g. `0-5`) without
+// checking it, or returns None if the text is not made of numbers.
+fn parse_index_range(part: &str) -> Option<(usize, usize)> {
+    let mut parts = part.splitn(2, '-');
+    let first: usize = parts.next()?.trim().parse().ok()?;
+
+    match parts.next() {
+        Some(last) => Some((first, last.trim().parse().ok()?)),
+        None => Some((first, first))
+    }
+}
+
+// Parses a comma separated list of track indices (e.g. `3`), index ranges
+// (e.g. `0-2`) and track names, which may contain `*` as a wildcard.
+fn select_tracks(track_names: &[&str], selection: &str) -> Result<Vec<usize>, Failure> {
+    let count = track_names.len();
+    let mut tracks: Vec<usize> = vec![];
+
+    for part in selection.split(',').map(|p| p.trim()) {
+        let selected: Vec<usize> = match parse_index_range(part) {
+            Some((first, last)) if first <= last && last < count => (first..=last).collect(),
+            Some(_) => return Err(Failure::Usage(format!("invalid track index or range {} (the song has {} tracks)", part, count))),
+            None => (0..count).filter(|&index| matches_wildcard(part, track_names[index])).collect()
+        };
+
+        for index in selected {
+            if !tracks.contains(&index) {
+                tracks.push(index);
+            }
+        }
+    }
+
+    if tracks.is_empty() {
+        return Err(Failure::Usage(format!("no tracks match {}", selection)));
+    }
+    Ok(tracks)
+}
+
+fn matches_wildcard(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let text = text.to_lowercase();
+    let parts: Vec<&str> = pattern.split('*').collect();
+
+    if parts.len() == 1 {
+        return pattern == text;
+    }
+
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !text.starts_with(first) || !text[first.len()..].ends_with(last) {
+        return false;
+    }
+
+    let mut rest = &text[first.len()..text.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false
+        }
+    }
+    true
+}
+
+// Parses an inclusive line range such as `16-31`, or `16-` for everything
+// from line 16 on, which ends at `u32::MAX`.
+fn parse_line_range(range: &str) -> Option<std::ops::Range<u32>> {
+    let mut parts = range.splitn(2, '-');
+    let first: u32 = parts.next()?.trim().parse().ok()?;
+
+    let last: u32 = match parts.next().map(|l| l.trim()) {
+        None => first,
+        Some("") => return first.checked_add(1).map(|_| first..u32::MAX),
+        Some(last) => last.parse().ok()?
+    };
+    if first > last {
+        return None;
+    }
+
+    Some(first..last.checked_add(1)?)
+}
+
+// Parses a size such as `128x96`.
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let mut parts = size.splitn(2, 'x');
+    let width: u32 = parts.next()?.trim().parse().ok()?;
+    let height: u32 = parts.next()?.trim().parse().ok()?;
+
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_list() {
+        assert_eq!(parse_index_list("3", 8), Some(vec![3]));
+        assert_eq!(parse_index_list("5-7, 0 ,6", 8), Some(vec![5, 6, 7, 0]));
+        assert_eq!(parse_index_list("2 - 2", 8), Some(vec![2]));
+
+        assert_eq!(parse_index_list("8", 8), None);
+        assert_eq!(parse_index_list("4-8", 8), None);
+        assert_eq!(parse_index_list("5-3", 8), None);
+        assert_eq!(parse_index_list("1,,2", 8), None);
+        assert_eq!(parse_index_list("-2", 8), None);
+        assert_eq!(parse_index_list("two", 8), None);
+    }
+
+    #[test]
+    fn tracks_by_index_and_name() {
+        let names = ["Kick", "Snare", "Bass 1", "Bass 2", "Master"];
+
+        assert_eq!(select_tracks(&names, "1").ok(), Some(vec![1]));
+        assert_eq!(select_tracks(&names, "3-4, 0").ok(), Some(vec![3, 4, 0]));
+        assert_eq!(select_tracks(&names, "3 - 4").ok(), Some(vec![3, 4]));
+        assert_eq!(select_tracks(&names, "bass*").ok(), Some(vec![2, 3]));
+        assert_eq!(select_tracks(&names, "snare, 1, Kick").ok(), Some(vec![1, 0]));
+
+        // the same ranges as for patterns are rejected
+        assert!(select_tracks(&names, "4-9").is_err());
+        assert!(select_tracks(&names, "4-2").is_err());
+        assert!(select_tracks(&names, "5").is_err());
+        assert!(select_tracks(&names, "Hats").is_err());
+    }
+
+    #[test]
+    fn wildcard() {
+        assert!(matches_wildcard("bass", "Bass"));
+        assert!(!matches_wildcard("bass", "Bass 1"));
+        assert!(matches_wildcard("bass*", "Bass 1"));
+        assert!(matches_wildcard("*1", "Bass 1"));
+        assert!(matches_wildcard("*", ""));
+        assert!(matches_wildcard("b*s*1", "Bass 1"));
+        assert!(matches_wildcard("*a*a*", "banana"));
+        assert!(!matches_wildcard("*a*a*a*a*", "banana"));
+        // the start and the end must not overlap
+        assert!(!matches_wildcard("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn line_range() {
+        assert_eq!(parse_line_range("16-31"), Some(16..32));
+        assert_eq!(parse_line_range("7"), Some(7..8));
+        assert_eq!(parse_line_range(" 4 - 4 "), Some(4..5));
+        assert_eq!(parse_line_range("16-"), Some(16..u32::MAX));
+
+        assert_eq!(parse_line_range("8-4"), None);
+        assert_eq!(parse_line_range("-4"), None);
+        assert_eq!(parse_line_range("4-x"), None);
+        assert_eq!(parse_line_range("4294967295"), None);
+        assert_eq!(parse_line_range("4294967295-"), None);
     }
 }
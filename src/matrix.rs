@@ -0,0 +1,79 @@
+extern crate image;
+
+use super::raster;
+use super::theme::*;
+
+const CHAR_WIDTH:  u32 = super::pattern_font::CHAR_WIDTH as u32;
+const CELL_WIDTH:  u32 = 24;
+const CELL_HEIGHT: u32 = 12;
+const CELL_GAP:    u32 = 2;
+const LABEL_WIDTH: u32 = 3 * CHAR_WIDTH + 2 * CELL_GAP; // e.g. 012
+
+// Renders an overview of the pattern sequence in the style of Renoise's
+// pattern matrix: one row per sequence entry, one cell per track.
+pub fn render(song: &super::renoise::Song) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    let entries = &song.pattern_sequence.sequence_entries;
+    let width: u32 = LABEL_WIDTH + song.tracks.len() as u32 * (CELL_WIDTH + CELL_GAP);
+    let height: u32 = CELL_GAP + entries.len() as u32 * (CELL_HEIGHT + CELL_GAP);
+
+    let mut imgbuf = image::ImageBuffer::from_pixel(width, height, COLOR_BACK.normal);
+
+    for (index, entry) in entries.iter().enumerate() {
+        let y: u32 = CELL_GAP + index as u32 * (CELL_HEIGHT + CELL_GAP);
+        let pattern = &song.pattern_pool.patterns[entry.pattern as usize];
+
+        let label = format!("{:03}", index);
+        super::renderer::render_label(&mut imgbuf, &label, CELL_GAP, y + (CELL_HEIGHT - CHAR_WIDTH) / 2, LABEL_WIDTH, &COLOR_DEFAULT.normal);
+
+        for (track_index, track_info) in song.tracks.iter().enumerate() {
+            let x: u32 = LABEL_WIDTH + track_index as u32 * (CELL_WIDTH + CELL_GAP);
+
+            let color = if track_index < pattern.tracks.len() && has_notes(song.pattern_track_lines(entry.pattern as usize, track_index)) {
+                let [r, g, b] = track_info.rgb();
+                image::Rgba([r, g, b, 255])
+            } else {
+                COLOR_BACK.highlighted
+            };
+            raster::fill_rect(&mut imgbuf, x, y, CELL_WIDTH, CELL_HEIGHT, &color);
+
+            if entry.muted_tracks.contains(&(track_index as u32)) {
+                hatch_cell(&mut imgbuf, x, y, COLOR_BACK.normal);
+            }
+
+            if track_index < pattern.tracks.len() && pattern.tracks[track_index].alias_pattern_index >= 0 {
+                mark_cell(&mut imgbuf, x, y, COLOR_DEFAULT.highlighted);
+            }
+        }
+    }
+
+    imgbuf
+}
+
+fn has_notes(lines: &[super::renoise::Line]) -> bool {
+    lines.iter().any(|line| line.note_columns.iter().any(|note_column| match note_column {
+        Some(note) => note.note.trim() != "" && note.note != "---",
+        None => false
+    }))
+}
+
+// Draws diagonal stripes across a cell, used for muted tracks.
+fn hatch_cell(imgbuf: &mut image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, x: u32, y: u32, color: image::Rgba<u8>) {
+    for cell_y in 0..CELL_HEIGHT {
+        for cell_x in 0..CELL_WIDTH {
+            if (cell_x + cell_y) % 4 == 0 {
+                imgbuf.put_pixel(x + cell_x, y + cell_y, color);
+            }
+        }
+    }
+}
+
+// Draws a small triangle into the top left corner of a cell, used for
+// aliased pattern tracks.
+fn mark_cell(imgbuf: &mut image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, x: u32, y: u32, color: image::Rgba<u8>) {
+    let size: u32 = CELL_HEIGHT / 2;
+    for cell_y in 0..size {
+        for cell_x in 0..size - cell_y {
+            imgbuf.put_pixel(x + cell_x, y + cell_y, color);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+// Templates for the names of output files, such as
+// `{song}/{pattern}-{pattern_name}.{ext}`.  Numbers are padded with zeros to
+// the same width for all files of a song, so that they sort in order even in
+// songs with 100 patterns or more.  The names stay below the output
+// directory: templates cannot be absolute or go up with `..`, and the values
+// filled in cannot either.
+
+use std::path::{Component, Path};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Placeholder {
+    Song,
+    Artist,
+    Pattern,
+    PatternName,
+    Seq,
+    Section,
+    Page,
+    Ext
+}
+
+const PLACEHOLDERS: [(&str, Placeholder); 8] = [
+    ("song", Placeholder::Song),
+    ("artist", Placeholder::Artist),
+    ("pattern", Placeholder::Pattern),
+    ("pattern_name", Placeholder::PatternName),
+    ("seq", Placeholder::Seq),
+    ("section", Placeholder::Section),
+    ("page", Placeholder::Page),
+    ("ext", Placeholder::Ext)
+];
+
+enum Part {
+    Text(String),
+    Placeholder(Placeholder)
+}
+
+pub struct NameTemplate {
+    parts: Vec<Part>
+}
+
+// What an output file shows.  The pattern of a sequence position is looked up
+// if not given.
+#[derive(Clone, Copy, Default)]
+pub struct Subject {
+    pub pattern: Option<usize>,
+    pub sequence_index: Option<usize>,
+    pub page: Option<usize>
+}
+
+impl Subject {
+    pub fn pattern(pattern: usize) -> Subject {
+        Subject { pattern: Some(pattern), ..Subject::default() }
+    }
+
+    pub fn sequence_index(sequence_index: usize) -> Subject {
+        Subject { sequence_index: Some(sequence_index), ..Subject::default() }
+    }
+
+    pub fn page(page: usize) -> Subject {
+        Subject { page: Some(page), ..Subject::default() }
+    }
+}
+
+impl NameTemplate {
+    pub fn parse(template: &str) -> Result<NameTemplate, Box<dyn std::error::Error>> {
+        let mut parts: Vec<Part> = vec![];
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => { bail!("Unclosed {{ in name template {}", template); }
+            };
+            let name = &rest[start + 1..end];
+            match PLACEHOLDERS.iter().find(|(n, _)| *n == name) {
+                Some(&(_, placeholder)) => parts.push(Part::Placeholder(placeholder)),
+                None => { bail!("Unknown placeholder {{{}}} in name template {}", name, template); }
+            }
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        // the values filled in are checked as well, but a template that always
+        // leaves is better reported right away
+        let example: String = parts.iter().map(|part| match part {
+            Part::Text(text) => text.as_str(),
+            Part::Placeholder(_) => "x"
+        }).collect();
+        if leaves_directory(&example) {
+            bail!("Name template {} leaves the output directory", template);
+        }
+
+        Ok(NameTemplate { parts })
+    }
+
+    // Fills in the placeholders.  Placeholders that do not apply to the file,
+    // such as `{seq}` for the image of a pattern, and empty names become `_`,
+    // and names taken from the song have characters that do not work in file
+    // names replaced.
+    pub fn expand(&self, song: &super::renoise::Song, subject: &Subject, extension: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let entries = &song.pattern_sequence.sequence_entries;
+        let pattern = subject.pattern.or_else(|| subject.sequence_index.and_then(|i| entries.get(i)).map(|e| e.pattern as usize));
+        let pattern_digits = digits(song.pattern_pool.patterns.len()).max(2);
+        let seq_digits = digits(entries.len()).max(3);
+
+        let name: String = self.parts.iter().map(|part| match part {
+            Part::Text(text) => text.clone(),
+            Part::Placeholder(placeholder) => file_name_safe(&match placeholder {
+                Placeholder::Song => song.global_song_data.song_name.clone(),
+                Placeholder::Artist => song.global_song_data.artist.clone(),
+                Placeholder::Pattern => pattern.map_or(String::new(), |p| format!("{:0width$}", p, width = pattern_digits)),
+                Placeholder::PatternName => pattern.and_then(|p| song.pattern_pool.patterns.get(p)).map_or(String::new(), |p| p.name.clone()),
+                Placeholder::Seq => subject.sequence_index.map_or(String::new(), |i| format!("{:0width$}", i, width = seq_digits)),
+                Placeholder::Section => subject.sequence_index.and_then(|i| entries.get(i)).map_or(String::new(), |e| e.section_name.clone()),
+                Placeholder::Page => subject.page.map_or(String::new(), |p| format!("{:02}", p)),
+                Placeholder::Ext => extension.to_string()
+            })
+        }).collect();
+        if leaves_directory(&name) {
+            bail!("File name {} leaves the output directory", name);
+        }
+
+        Ok(name)
+    }
+}
+
+fn leaves_directory(name: &str) -> bool {
+    Path::new(name).components().any(|component| match component {
+        Component::Prefix(_) | Component::RootDir | Component::ParentDir => true,
+        Component::CurDir | Component::Normal(_) => false
+    })
+}
+
+// The number of digits of the highest of `count` indices.
+fn digits(count: usize) -> usize {
+    count.saturating_sub(1).to_string().len()
+}
+
+fn file_name_safe(name: &str) -> String {
+    match name.trim() {
+        "" | "." | ".." => String::from("_"),
+        name => name.chars().map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::renoise::{Song, TestSong};
+
+    // A song without tracks, with the given pattern names and sequence
+    // entries, as pattern and section name.
+    fn song(song_name: &str, pattern_names: &[&'static str], sequence: &[(usize, &'static str)]) -> Song {
+        let mut song = TestSong::default().global("SongName", song_name).global("Artist", "Someone");
+        for &name in pattern_names.iter() {
+            song = song.pattern(name, 64);
+        }
+        for &(pattern, section_name) in sequence.iter() {
+            song = song.sequence(pattern, section_name);
+        }
+        song.song()
+    }
+
+    fn expand(template: &str, song: &Song, subject: Subject) -> String {
+        NameTemplate::parse(template).unwrap().expand(song, &subject, "png").unwrap()
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(NameTemplate::parse("{song}/{pattern}-{pattern_name}.{ext}").is_ok());
+        assert!(NameTemplate::parse("plain").is_ok());
+
+        assert!(NameTemplate::parse("{song").is_err());
+        assert!(NameTemplate::parse("{title}.{ext}").is_err());
+        assert!(NameTemplate::parse("{}.{ext}").is_err());
+    }
+
+    #[test]
+    fn templates_stay_in_the_directory() {
+        assert!(NameTemplate::parse("/tmp/{pattern}.{ext}").is_err());
+        assert!(NameTemplate::parse("../{pattern}.{ext}").is_err());
+        assert!(NameTemplate::parse("{song}/../../{pattern}.{ext}").is_err());
+        assert!(NameTemplate::parse("./{song}/{pattern}.{ext}").is_ok());
+
+        // values taken from the song cannot leave either
+        let song = song("..", &["../../etc/passwd", "."], &[]);
+        assert_eq!(expand("{song}/{pattern_name}", &song, Subject::pattern(0)), "_/.._.._etc_passwd");
+        assert_eq!(expand("{song}/{pattern_name}", &song, Subject::pattern(1)), "_/_");
+    }
+
+    #[test]
+    fn placeholders() {
+        let song = song("Demo", &["Intro", "Lead"], &[(0, "Start"), (1, ""), (1, "End")]);
+
+        assert_eq!(expand("{song}/{pattern}-{pattern_name}.{ext}", &song, Subject::pattern(1)), "Demo/01-Lead.png");
+        assert_eq!(expand("{artist} {seq} {pattern} {section}", &song, Subject::sequence_index(2)), "Someone 002 01 End");
+        assert_eq!(expand("page-{page}.{ext}", &song, Subject::page(3)), "page-03.png");
+    }
+
+    #[test]
+    fn missing_values_become_underscores() {
+        let song = song("  ", &["", "Name: a/b?"], &[(0, "")]);
+
+        assert_eq!(expand("{song}-{seq}-{section}", &song, Subject::pattern(0)), "_-_-_");
+        assert_eq!(expand("{pattern_name}", &song, Subject::sequence_index(0)), "_");
+        assert_eq!(expand("{pattern_name}", &song, Subject::pattern(1)), "Name_ a_b_");
+        assert_eq!(expand("{pattern}", &song, Subject::page(0)), "_");
+    }
+
+    #[test]
+    fn numbers_are_padded_for_the_song() {
+        let names = vec![""; 120];
+        let sequence = vec![(7, ""); 1001];
+        let song = song("Long", &names, &sequence);
+
+        assert_eq!(expand("{pattern}", &song, Subject::pattern(7)), "007");
+        assert_eq!(expand("{seq}", &song, Subject::sequence_index(12)), "0012");
+        assert_eq!(expand("{pattern}", &song, Subject::sequence_index(1000)), "007");
+
+        assert_eq!(digits(0), 1);
+        assert_eq!(digits(10), 1);
+        assert_eq!(digits(11), 2);
+        assert_eq!(digits(1000), 3);
+    }
+}
@@ -1,10 +1,24 @@
 pub const CHAR_WIDTH: usize = 8;
 
+// Maps characters outside of ASCII to their code page 437 equivalent, which
+// is what `char` expects.
+pub fn code(c: char) -> u8 {
+    match c {
+        '→' => 0x1a,
+        '←' => 0x1b,
+        c if c.is_ascii() => c as u8,
+        _ => 0xff
+    }
+}
+
 pub fn char(c: u8) -> [u8; CHAR_WIDTH] {
     match c {
+        0x1a => [0x00, 0x18, 0x0c, 0xfe, 0x0c, 0x18, 0x00, 0x00],
+        0x1b => [0x00, 0x30, 0x60, 0xfe, 0x60, 0x30, 0x00, 0x00],
         0x20 => [0x00, 0x00, 0x00, 0x54, 0x00, 0x00, 0x00, 0x00],
         0x21 => [0x00, 0x54, 0x00, 0x54, 0x00, 0x54, 0x00, 0x00],
         0x23 => [0x00, 0x48, 0xfc, 0x48, 0xfc, 0x48, 0x00, 0x00],
+        0x2b => [0x00, 0x30, 0x30, 0xfc, 0x30, 0x30, 0x00, 0x00],
         0x2d => [0x00, 0x00, 0x00, 0x78, 0x00, 0x00, 0x00, 0x00],
         0x2e => [0x00, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00, 0x00],
         0x30 => [0x78, 0xcc, 0xdc, 0xfc, 0xec, 0xcc, 0x78, 0x00],
@@ -0,0 +1,125 @@
+extern crate flate2;
+extern crate image;
+
+use std::io::Write;
+
+// Object numbers of the objects every document has.  The objects of the
+// pages follow, three per page: the page, its content stream and its image.
+const CATALOG: usize = 1;
+const PAGES:   usize = 2;
+const INFO:    usize = 3;
+const FIRST_PAGE_OBJECT: usize = 4;
+
+// Writes a PDF document with one image per page, each covering the whole
+// page.  Pages are written out as they are added, so only one page image
+// needs to be kept around at a time.
+pub struct Writer<W: Write> {
+    out: W,
+    position: usize,
+    offsets: Vec<usize>, // of the objects written so far, by object number
+    page_size: (f32, f32), // in points
+    title: String,
+    number_of_pages: usize
+}
+
+impl<W: Write> Writer<W> {
+    // Starts a document with pages of `page_size` points (1/72 inch).
+    pub fn new(out: W, page_size: (f32, f32), title: &str) -> std::io::Result<Writer<W>> {
+        let mut writer = Writer {
+            out,
+            position: 0,
+            offsets: vec![0; FIRST_PAGE_OBJECT],
+            page_size,
+            title: title.to_string(),
+            number_of_pages: 0
+        };
+
+        // the binary comment tells tools that the file is not plain text
+        writer.write(b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n")?;
+
+        Ok(writer)
+    }
+
+    pub fn add_page(&mut self, image: &image::ImageBuffer<image::Rgb<u8>, Vec<u8>>) -> std::io::Result<()> {
+        let page = FIRST_PAGE_OBJECT + 3 * self.number_of_pages;
+        let (width, height) = self.page_size;
+        self.number_of_pages += 1;
+
+        self.object(page, format!(
+            "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources << /XObject << /Im0 {} 0 R >> >> /Contents {} 0 R >>",
+            PAGES, width, height, page + 2, page + 1
+        ).as_bytes())?;
+
+        let contents = format!("q {} 0 0 {} 0 0 cm /Im0 Do Q", width, height);
+        self.stream(page + 1, "", contents.as_bytes())?;
+
+        let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(image)?;
+        let dictionary = format!(
+            "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /FlateDecode",
+            image.width(), image.height()
+        );
+        self.stream(page + 2, &dictionary, &encoder.finish()?)
+    }
+
+    // Writes the objects tying the pages together and the cross-reference
+    // table, and returns the output.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let kids: Vec<String> = (0..self.number_of_pages).map(|index| format!("{} 0 R", FIRST_PAGE_OBJECT + 3 * index)).collect();
+
+        self.object(CATALOG, format!("<< /Type /Catalog /Pages {} 0 R >>", PAGES).as_bytes())?;
+        self.object(PAGES, format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), self.number_of_pages).as_bytes())?;
+        self.object(INFO, format!("<< /Title {} /Creator (patren) >>", text_string(&self.title)).as_bytes())?;
+
+        let xref_position = self.position;
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len());
+        for offset in self.offsets[1..].iter() {
+            xref.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        xref.push_str(&format!(
+            "trailer\n<< /Size {} /Root {} 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len(), CATALOG, INFO, xref_position
+        ));
+        self.write(xref.as_bytes())?;
+
+        Ok(self.out)
+    }
+
+    fn object(&mut self, number: usize, body: &[u8]) -> std::io::Result<()> {
+        self.begin_object(number)?;
+        self.write(body)?;
+        self.write(b"\nendobj\n")
+    }
+
+    fn stream(&mut self, number: usize, dictionary: &str, data: &[u8]) -> std::io::Result<()> {
+        self.begin_object(number)?;
+        self.write(format!("<< {} /Length {} >>\nstream\n", dictionary, data.len()).as_bytes())?;
+        self.write(data)?;
+        self.write(b"\nendstream\nendobj\n")
+    }
+
+    fn begin_object(&mut self, number: usize) -> std::io::Result<()> {
+        if self.offsets.len() <= number {
+            self.offsets.resize(number + 1, 0);
+        }
+        self.offsets[number] = self.position;
+        self.write(format!("{} 0 obj\n", number).as_bytes())
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.out.write_all(data)?;
+        self.position += data.len();
+        Ok(())
+    }
+}
+
+// Encodes text as a PDF text string in UTF-16, which works for any
+// characters in song names.
+fn text_string(text: &str) -> String {
+    let mut string = String::from("<FEFF");
+    for unit in text.encode_utf16() {
+        string.push_str(&format!("{:04X}", unit));
+    }
+    string.push('>');
+    string
+}
@@ -0,0 +1,160 @@
+extern crate image;
+
+use super::raster;
+use super::theme::*;
+
+const CHAR_WIDTH:  u32 = super::pattern_font::CHAR_WIDTH as u32;
+const LINE_WIDTH:  u32 = 6;
+const ROW_HEIGHT:  u32 = 5;
+const LABEL_WIDTH: u32 = 4 * CHAR_WIDTH; // e.g. C-4
+const MARGIN:      u32 = 2;             // semitones above and below the used range
+
+// What the note bars are coloured by.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorMode {
+    Track,
+    Instrument
+}
+
+struct Note {
+    track: usize,
+    instrument: Option<u32>,
+    pitch: u32,
+    start: u32,
+    end: u32
+}
+
+pub fn render(song: &super::renoise::Song, pattern: usize, color_mode: ColorMode) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    render_patterns(song, &[pattern], color_mode)
+}
+
+// Renders the patterns of the whole song in the order of the pattern
+// sequence.
+pub fn render_song(song: &super::renoise::Song, color_mode: ColorMode) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    let patterns: Vec<usize> = song.pattern_sequence.sequence_entries.iter().map(|entry| entry.pattern as usize).collect();
+
+    render_patterns(song, &patterns, color_mode)
+}
+
+fn render_patterns(song: &super::renoise::Song, patterns: &[usize], color_mode: ColorMode) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    let notes = collect_notes(song, patterns);
+    let number_of_lines: u32 = patterns.iter().map(|&p| song.pattern_pool.patterns[p].number_of_lines).sum();
+
+    let lowest: u32 = notes.iter().map(|n| n.pitch).min().unwrap_or(48).saturating_sub(MARGIN);
+    let highest: u32 = notes.iter().map(|n| n.pitch).max().unwrap_or(59) + MARGIN;
+
+    let width: u32 = LABEL_WIDTH + number_of_lines * LINE_WIDTH;
+    let height: u32 = (highest - lowest + 1) * ROW_HEIGHT;
+
+    let mut imgbuf = image::ImageBuffer::from_pixel(width, height, COLOR_BACK.normal);
+
+    // render pitch grid, with the rows of C highlighted and labelled
+    for pitch in lowest..=highest {
+        let y = pitch_y(pitch, highest);
+        if pitch % 12 == 0 {
+            raster::fill_rect(&mut imgbuf, LABEL_WIDTH, y, width - LABEL_WIDTH, ROW_HEIGHT, &COLOR_BACK.highlighted);
+            let label = format!("C-{}", pitch / 12);
+            super::renderer::render_label(&mut imgbuf, &label, 0, (y + ROW_HEIGHT).saturating_sub(CHAR_WIDTH), LABEL_WIDTH, &COLOR_DEFAULT.normal);
+        }
+    }
+
+    // render beat lines, and pattern boundaries a bit brighter
+    let mut pattern_start: u32 = 0;
+    for &pattern in patterns.iter() {
+        let pat = &song.pattern_pool.patterns[pattern];
+        for line in (0..pat.number_of_lines).step_by(song.global_song_data.lines_per_beat.max(1) as usize) {
+            let color = if line == 0 { COLOR_DEFAULT.normal } else { COLOR_BACK.highlighted };
+            raster::fill_rect(&mut imgbuf, LABEL_WIDTH + (pattern_start + line) * LINE_WIDTH, 0, 1, height, &color);
+        }
+        pattern_start += pat.number_of_lines;
+    }
+
+    // render notes
+    for note in notes.iter() {
+        let color = match color_mode {
+            ColorMode::Track => {
+                let [r, g, b] = song.tracks[note.track].rgb();
+                image::Rgba([r, g, b, 255])
+            },
+            ColorMode::Instrument => match note.instrument {
+                Some(instrument) => instrument_color(instrument),
+                None => COLOR_DEFAULT.normal
+            }
+        };
+
+        let x: u32 = LABEL_WIDTH + note.start * LINE_WIDTH;
+        let length: u32 = (note.end - note.start) * LINE_WIDTH;
+        raster::fill_rect(&mut imgbuf, x + 1, pitch_y(note.pitch, highest), length - 1, ROW_HEIGHT - 1, &color);
+    }
+
+    imgbuf
+}
+
+fn pitch_y(pitch: u32, highest: u32) -> u32 {
+    (highest - pitch) * ROW_HEIGHT
+}
+
+// Collects the notes played in the given patterns one after another.  A note
+// lasts until the next note or note-off in the same note column, or until the
+// end of the last pattern.
+fn collect_notes(song: &super::renoise::Song, patterns: &[usize]) -> Vec<Note> {
+    let mut notes: Vec<Note> = vec![];
+
+    for (track_index, track_info) in song.tracks.iter().enumerate() {
+        for column in 0..track_info.number_of_visible_note_columns as usize {
+            let mut playing: Option<Note> = None;
+            let mut pattern_start: u32 = 0;
+
+            for &pattern in patterns.iter() {
+                let pat = &song.pattern_pool.patterns[pattern];
+                if track_index >= pat.tracks.len() {
+                    pattern_start += pat.number_of_lines;
+                    continue;
+                }
+
+                let mut lines: Vec<&super::renoise::Line> = song.pattern_track_lines(pattern, track_index).iter()
+                    .filter(|line| line.index < pat.number_of_lines)
+                    .collect();
+                lines.sort_by_key(|line| line.index);
+
+                for line in lines {
+                    let note_column = match line.note_columns.get(column) {
+                        Some(Some(note_column)) => note_column,
+                        _ => { continue; }
+                    };
+
+                    let pitch = note_column.pitch();
+                    if pitch.is_none() && !note_column.is_note_off() {
+                        continue;
+                    }
+
+                    let time: u32 = pattern_start + line.index;
+                    if let Some(mut note) = playing.take() {
+                        note.end = time;
+                        notes.push(note);
+                    }
+
+                    if let Some(pitch) = pitch {
+                        playing = Some(Note {
+                            track: track_index,
+                            instrument: note_column.instrument_number(),
+                            pitch,
+                            start: time,
+                            end: time
+                        });
+                    }
+                }
+
+                pattern_start += pat.number_of_lines;
+            }
+
+            if let Some(mut note) = playing.take() {
+                note.end = pattern_start;
+                notes.push(note);
+            }
+        }
+    }
+
+    notes.retain(|note| note.end > note.start);
+    notes
+}
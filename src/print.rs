@@ -0,0 +1,167 @@
+extern crate image;
+
+use super::canvas::{Canvas, Offset};
+use super::layout::Layout;
+use super::renderer::{self, Flow, MuteStyle, NoteColors, HEADER_HEIGHT, LINE_HEIGHT};
+use super::theme::*;
+
+const CHAR_WIDTH:     u32 = super::pattern_font::CHAR_WIDTH as u32;
+const MARGIN:         u32 = 4 * CHAR_WIDTH;
+const RULE_Y:         u32 = LINE_HEIGHT + 2; // of the line below the title
+const TITLE_HEIGHT:   u32 = 2 * LINE_HEIGHT;
+const FOOTER_HEIGHT:  u32 = 2 * LINE_HEIGHT;
+const HEADING_HEIGHT: u32 = 2 * LINE_HEIGHT; // sequence position, pattern and section
+const MIN_WIDTH:      u32 = 80 * CHAR_WIDTH;
+const MIN_LINES:      u32 = 8;   // fewer lines of a pattern go on the next page
+const PRINT_DPI:      u32 = 150; // the least resolution the pages are printed at
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Paper {
+    A4,
+    Letter
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Orientation {
+    Portrait,
+    Landscape
+}
+
+impl Paper {
+    // Returns the size of the paper in points (1/72 inch).
+    pub fn size(self, orientation: Orientation) -> (f32, f32) {
+        let (width, height) = match self {
+            Paper::A4 => (595.0, 842.0),
+            Paper::Letter => (612.0, 792.0)
+        };
+
+        match orientation {
+            Orientation::Portrait => (width, height),
+            Orientation::Landscape => (height, width)
+        }
+    }
+}
+
+// Some lines of the pattern played at a position of the pattern sequence,
+// placed on a page at `y`.
+struct Piece {
+    sequence_index: usize,
+    lines: std::ops::Range<u32>,
+    y: u32
+}
+
+// How the song is split into pages.  The pages are as wide as the widest
+// pattern (in pixels of the renderer) and as high as the paper's aspect ratio
+// makes them.  Patterns that do not fit on a page are continued on the next
+// one, below the track headers again.
+pub struct PrintLayout {
+    pub width: u32,
+    pub height: u32,
+    pages: Vec<Vec<Piece>>,
+    flows: Vec<super::flow::EntryFlow>
+}
+
+impl PrintLayout {
+    pub fn new(song: &super::renoise::Song, paper_size: (f32, f32), note_colors: NoteColors, flow: Flow) -> PrintLayout {
+        let width: u32 = (2 + Layout::for_song(song).width + flow.margin_width() + 2 * MARGIN).max(MIN_WIDTH);
+        let height: u32 = (width as f32 * paper_size.1 / paper_size.0).round() as u32;
+        let (top, bottom) = (MARGIN + TITLE_HEIGHT, height - MARGIN - FOOTER_HEIGHT);
+
+        let mut pages: Vec<Vec<Piece>> = vec![vec![]];
+        let mut y: u32 = top;
+        for (sequence_index, entry) in song.pattern_sequence.sequence_entries.iter().enumerate() {
+            let pattern = entry.pattern as usize;
+            let tracks: Vec<usize> = (0..song.pattern_pool.patterns[pattern].tracks.len()).collect();
+            let number_of_lines = song.pattern_pool.patterns[pattern].number_of_lines;
+            let piece_height = |lines: std::ops::Range<u32>| HEADING_HEIGHT + renderer::region_size(song, pattern, &tracks, lines, note_colors).1;
+
+            let mut start: u32 = 0;
+            while start < number_of_lines {
+                let available = bottom.saturating_sub(y);
+                let mut count: u32 = (available.saturating_sub(HEADING_HEIGHT + HEADER_HEIGHT) / LINE_HEIGHT).min(number_of_lines - start);
+                while count > 0 && piece_height(start..start + count) > available {
+                    count -= 1;
+                }
+
+                let page_is_empty = pages.last().unwrap().is_empty();
+                if count < MIN_LINES.min(number_of_lines - start) && !page_is_empty {
+                    pages.push(vec![]);
+                    y = top;
+                    continue;
+                }
+
+                // a page too small for even a single line gets one anyway,
+                // cut off at the bottom
+                let count = count.max(1);
+                pages.last_mut().unwrap().push(Piece { sequence_index, lines: start..start + count, y });
+                y += piece_height(start..start + count);
+                start += count;
+            }
+        }
+
+        PrintLayout { width, height, pages, flows: flow.sequence_flow(song) }
+    }
+
+    pub fn number_of_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    // Renders a page with the song title at the top and the page number at
+    // the bottom, annotating the flow if the layout was made for it.
+    pub fn render_page(&self, song: &super::renoise::Song, page: usize, mute_style: MuteStyle, note_colors: NoteColors) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+        let mut imgbuf = image::ImageBuffer::new(self.width, self.height);
+        let text_width = self.width - 2 * MARGIN;
+
+        let title = format!("{} BY {}", song.global_song_data.song_name, song.global_song_data.artist).to_uppercase();
+        renderer::render_label(&mut imgbuf, &title, MARGIN, MARGIN, text_width, &COLOR_DEFAULT.highlighted);
+        imgbuf.draw_line(MARGIN, MARGIN + RULE_Y, self.width - MARGIN - 1, MARGIN + RULE_Y, &COLOR_DEFAULT.normal);
+
+        let page_number = format!("PAGE {} OF {}", page + 1, self.pages.len());
+        let page_number_x = self.width - MARGIN - page_number.len() as u32 * CHAR_WIDTH;
+        renderer::render_label(&mut imgbuf, &page_number, page_number_x, self.height - MARGIN - CHAR_WIDTH, text_width, &COLOR_DEFAULT.normal);
+
+        for piece in self.pages[page].iter() {
+            let entry = &song.pattern_sequence.sequence_entries[piece.sequence_index];
+            let mut heading = format!("{:03} PATTERN {:02} {}", piece.sequence_index, entry.pattern, entry.section_name.to_uppercase());
+            if piece.lines.start > 0 {
+                heading.push_str(&format!(" (FROM LINE {:02X})", piece.lines.start));
+            }
+            renderer::render_label(&mut imgbuf, &heading, MARGIN, piece.y, text_width, &COLOR_DEFAULT.highlighted);
+
+            let mut canvas = Offset::new(&mut imgbuf, MARGIN, piece.y + HEADING_HEIGHT);
+            renderer::draw_sequence_entry(&mut canvas, song, piece.sequence_index, piece.lines.clone(), mute_style, note_colors, self.flows.get(piece.sequence_index));
+        }
+
+        imgbuf
+    }
+}
+
+// Writes the whole song as a PDF document with a page per page of the print
+// layout, and returns the output.
+pub fn write_pdf<W: std::io::Write>(song: &super::renoise::Song, out: W, paper: Paper, orientation: Orientation, mute_style: MuteStyle, note_colors: NoteColors, flow: Flow) -> Result<W, Box<dyn std::error::Error>> {
+    let paper_size = paper.size(orientation);
+    let layout = PrintLayout::new(song, paper_size, note_colors, flow);
+    let title = format!("{} by {}", song.global_song_data.song_name, song.global_song_data.artist);
+
+    // scale the pages up by whole pixels, so that the glyphs stay sharp
+    let paper_width_inches = paper_size.0 / 72.0;
+    let scale: u32 = ((PRINT_DPI as f32 * paper_width_inches / layout.width as f32).ceil() as u32).max(1);
+
+    let mut writer = super::pdf::Writer::new(out, paper_size, &title)?;
+    for page in 0..layout.number_of_pages() {
+        let image = flatten(&layout.render_page(song, page, mute_style, note_colors), COLOR_BACK.normal);
+        writer.add_page(&image::imageops::resize(&image, layout.width * scale, layout.height * scale, image::FilterType::Nearest))?;
+    }
+
+    Ok(writer.finish()?)
+}
+
+// Draws an image over a background colour, leaving out the alpha channel.
+fn flatten(imgbuf: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, background: image::Rgba<u8>) -> image::ImageBuffer<image::Rgb<u8>, Vec<u8>> {
+    image::ImageBuffer::from_fn(imgbuf.width(), imgbuf.height(), |x, y| {
+        let pixel = imgbuf.get_pixel(x, y);
+        let alpha = pixel[3] as u32;
+        let blend = |channel: usize| ((pixel[channel] as u32 * alpha + background[channel] as u32 * (255 - alpha)) / 255) as u8;
+        image::Rgb([blend(0), blend(1), blend(2)])
+    })
+}
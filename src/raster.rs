@@ -0,0 +1,141 @@
+extern crate image;
+
+use std::sync::OnceLock;
+
+const CHAR_WIDTH: usize = super::pattern_font::CHAR_WIDTH;
+
+// The pattern font as row bitmaps, indexed by code page 437 code, so that
+// drawing text does not go through `pattern_font::char` for every glyph.
+fn glyphs() -> &'static [[u8; CHAR_WIDTH]] {
+    static GLYPHS: OnceLock<Vec<[u8; CHAR_WIDTH]>> = OnceLock::new();
+
+    GLYPHS.get_or_init(|| (0..=255u8).map(super::pattern_font::char).collect())
+}
+
+// Draws text in the pattern font, given as code page 437 codes.  Only the
+// pixels the glyphs set are written, straight into the pixel buffer and
+// clipped to the image.
+pub fn draw_text<I: IntoIterator<Item = u8>>(imgbuf: &mut image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, codes: I, x: u32, y: u32, color: &image::Rgba<u8>) {
+    let (width, height) = (imgbuf.width() as usize, imgbuf.height() as usize);
+    let (x, y) = (x as usize, y as usize);
+    if y >= height {
+        return;
+    }
+
+    let glyphs = glyphs();
+    let rows = CHAR_WIDTH.min(height - y);
+    let buffer: &mut [u8] = imgbuf;
+
+    for (index, code) in codes.into_iter().enumerate() {
+        let glyph_x = x + index * CHAR_WIDTH;
+        if glyph_x >= width {
+            break;
+        }
+        let visible = CHAR_WIDTH.min(width - glyph_x);
+
+        for (row, &bits) in glyphs[code as usize][..rows].iter().enumerate() {
+            let row_start = ((y + row) * width + glyph_x) * 4;
+            let pixels = &mut buffer[row_start..row_start + visible * 4];
+
+            let mut bits = bits;
+            while bits != 0 {
+                let pixel_x = bits.leading_zeros() as usize;
+                if pixel_x >= visible {
+                    break;
+                }
+                pixels[pixel_x * 4..pixel_x * 4 + 4].copy_from_slice(&color.0);
+                bits &= !(0x80 >> pixel_x);
+            }
+        }
+    }
+}
+
+// Draws a glyph pixel by pixel.  This is how glyphs were drawn before
+// `draw_text`, and is kept around to compare against in the benchmarks.
+pub fn draw_glyph_per_pixel(imgbuf: &mut image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, glyph: [u8; CHAR_WIDTH], x: u32, y: u32, color: &image::Rgba<u8>) {
+    for (index, row) in glyph.iter().enumerate() {
+        let real_y: u32 = y + index as u32;
+        if real_y >= imgbuf.height() {
+            break;
+        }
+
+        let mut num = *row;
+        let mut x_offset = 0;
+        while num != 0 {
+            if num >> 7 == 1 && x + x_offset < imgbuf.width() {
+                imgbuf.put_pixel(x + x_offset, real_y, *color);
+            }
+            x_offset += 1;
+            num <<= 1;
+        }
+    }
+}
+
+// Fills a rectangle with a colour, clipped to the image.
+pub fn fill_rect(imgbuf: &mut image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, x: u32, y: u32, width: u32, height: u32, color: &image::Rgba<u8>) {
+    let image_width = imgbuf.width() as usize;
+    let (left, right) = ((x as usize).min(image_width), ((x + width) as usize).min(image_width));
+    let (top, bottom) = (y.min(imgbuf.height()) as usize, (y + height).min(imgbuf.height()) as usize);
+    if left == right || top == bottom {
+        return;
+    }
+
+    // fill the first row pixel by pixel, and copy it to the others
+    let buffer: &mut [u8] = imgbuf;
+    let first_row = top * image_width * 4;
+    for pixel in buffer[first_row + left * 4..first_row + right * 4].chunks_exact_mut(4) {
+        pixel.copy_from_slice(&color.0);
+    }
+    for row in top + 1..bottom {
+        let row_start = row * image_width * 4;
+        buffer.copy_within(first_row + left * 4..first_row + right * 4, row_start + left * 4);
+    }
+}
+
+// Text rasterised once to be drawn many times over, such as the dots of empty
+// cells.  Only the pixels the glyphs set are kept, as runs of pixels: their
+// row, first column and length.
+pub struct Stamp {
+    runs: Vec<(usize, usize, usize)>,
+    color_row: Vec<u8>
+}
+
+impl Stamp {
+    pub fn text(codes: &[u8], color: &image::Rgba<u8>) -> Stamp {
+        let glyphs = glyphs();
+        let mut rows: Vec<Vec<(usize, usize, usize)>> = vec![vec![]; CHAR_WIDTH];
+
+        for (index, &code) in codes.iter().enumerate() {
+            for (row, (&bits, runs)) in glyphs[code as usize].iter().zip(rows.iter_mut()).enumerate() {
+                for pixel_x in (0..CHAR_WIDTH).filter(|pixel_x| bits & (0x80 >> pixel_x) != 0) {
+                    let x = index * CHAR_WIDTH + pixel_x;
+                    match runs.last_mut() {
+                        Some((_, start, length)) if *start + *length == x => *length += 1,
+                        _ => runs.push((row, x, 1))
+                    }
+                }
+            }
+        }
+        let runs = rows.concat();
+
+        let longest = runs.iter().map(|&(_, _, length)| length).max().unwrap_or(0);
+        Stamp { runs, color_row: color.0.repeat(longest) }
+    }
+}
+
+// Draws a stamp with its top left corner at `x`, `y`, clipped to the image.
+pub fn draw_stamp(imgbuf: &mut image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, stamp: &Stamp, x: u32, y: u32) {
+    let (width, height) = (imgbuf.width() as usize, imgbuf.height() as usize);
+    let (x, y) = (x as usize, y as usize);
+    let buffer: &mut [u8] = imgbuf;
+
+    for &(row, start, length) in stamp.runs.iter() {
+        let (left, top) = (x + start, y + row);
+        if left >= width || top >= height {
+            continue;
+        }
+        let length = length.min(width - left);
+        let run_start = (top * width + left) * 4;
+        buffer[run_start..run_start + length * 4].copy_from_slice(&stamp.color_row[..length * 4]);
+    }
+}
@@ -1,252 +1,471 @@
 extern crate image;
 
-struct ColorPair {
-    normal: image::Rgba<u8>,
-    highlighted: image::Rgba<u8>
+use super::canvas::Canvas;
+use super::flow::EntryFlow;
+use super::layout::{ColumnKind, Layout, TrackLayout, TRACK_SPACING_X};
+use super::theme::*;
+
+const CHAR_WIDTH:      u32 = super::pattern_font::CHAR_WIDTH as u32;
+const TRACK_SPACING_Y: u32 = 2;
+
+pub const LINE_HEIGHT:   u32 = CHAR_WIDTH + TRACK_SPACING_Y;
+pub const HEADER_HEIGHT: u32 = CHAR_WIDTH + 2 * TRACK_SPACING_Y + 2; // track name and colour bar
+const SEPARATOR_HEIGHT:  u32 = 2 * LINE_HEIGHT;
+
+const LEGEND_ENTRY_WIDTH: u32 = 5 * CHAR_WIDTH; // colour swatch and e.g. 0A
+const FLOW_MARGIN_WIDTH:  u32 = 4 * CHAR_WIDTH; // e.g. →10
+
+const MUTED_ALPHA:    u8 = 0x50;
+const UNPLAYED_ALPHA: u8 = 0x50;
+
+// How tracks muted in a sequence slot are drawn.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MuteStyle {
+    Dim,
+    Hide
 }
 
-impl ColorPair {
-    fn get(&self, highlighted: bool) -> image::Rgba<u8> {
-        if highlighted { self.highlighted }
-        else { self.normal }
-    }
+// What the notes and instrument numbers in note columns are coloured by.
+// With `Instrument`, a legend of the instruments is drawn below the pattern.
+#[derive(Clone, Copy, PartialEq)]
+pub enum NoteColors {
+    Default,
+    Instrument
 }
 
-static COLOR_BACK:      ColorPair = ColorPair { normal: image::Rgba([0x15, 0x15, 0x15, 255]), highlighted: image::Rgba([0x29, 0x29, 0x29, 255]) };
-static COLOR_DEFAULT:   ColorPair = ColorPair { normal: image::Rgba([0x94, 0x94, 0x94, 255]), highlighted: image::Rgba([0xFF, 0xFF, 0xFF, 255]) };
-static COLOR_VOLUME:    ColorPair = ColorPair { normal: image::Rgba([0xD4, 0xCE, 0x2A, 255]), highlighted: image::Rgba([0xBF, 0xAE, 0x25, 255]) };
-static COLOR_PANNING:   ColorPair = ColorPair { normal: image::Rgba([0x9D, 0xD6, 0x8C, 255]), highlighted: image::Rgba([0x81, 0xAF, 0x72, 255]) };
-static COLOR_PITCH:     ColorPair = ColorPair { normal: image::Rgba([0xB4, 0x4F, 0x21, 255]), highlighted: image::Rgba([0x9B, 0x44, 0x1D, 255]) };
-static COLOR_DELAY:     ColorPair = ColorPair { normal: image::Rgba([0x42, 0xC1, 0xEA, 255]), highlighted: image::Rgba([0x3D, 0xB4, 0xDA, 255]) };
-static COLOR_GLOBAL_FX: ColorPair = ColorPair { normal: image::Rgba([0xFD, 0x97, 0x14, 255]), highlighted: image::Rgba([0xC6, 0x76, 0x10, 255]) };
-static COLOR_OTHER_FX:  ColorPair = ColorPair { normal: image::Rgba([0xBA, 0x68, 0xBB, 255]), highlighted: image::Rgba([0x9A, 0x56, 0x9B, 255]) };
-static COLOR_DSP_FX:    ColorPair = ColorPair { normal: image::Rgba([0xDB, 0xDB, 0xDB, 255]), highlighted: image::Rgba([0xE5, 0xE5, 0xE5, 255]) };
-static COLOR_UNUSED_FX: ColorPair = ColorPair { normal: image::Rgba([0x9C, 0x9C, 0x9C, 255]), highlighted: image::Rgba([0x9C, 0x9C, 0x9C, 255]) };
+// Whether patterns played in sequence get a margin on the right showing where
+// ZB commands break them and where playback continues in the next pattern,
+// and ZD delays.  The lines skipped by breaks are greyed out.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Flow {
+    Plain,
+    Annotated
+}
 
-const CHAR_WIDTH:         u32 = super::pattern_font::CHAR_WIDTH as u32;
-const TRACK_SPACING_X:    u32 = 6;
-const TRACK_SPACING_X_FX: u32 = 3;
-const TRACK_WIDTH_NOTE:   u32 = TRACK_SPACING_X_FX + 5 * CHAR_WIDTH; // e.g. C-500
-const TRACK_WIDTH_VOL:    u32 = TRACK_SPACING_X_FX + 2 * CHAR_WIDTH; // e.g. 7F
-const TRACK_WIDTH_FX:     u32 = TRACK_SPACING_X_FX + 4 * CHAR_WIDTH; // e.g. ZT04
+impl Flow {
+    pub fn margin_width(self) -> u32 {
+        match self {
+            Flow::Plain => 0,
+            Flow::Annotated => FLOW_MARGIN_WIDTH
+        }
+    }
 
-const TRACK_SPACING_Y: u32 = 2;
+    // The flow of every position of the pattern sequence to annotate, from
+    // `flow::sequence_flow`, or none.  Following the breaks takes the whole
+    // song, so this is done once for all positions drawn.
+    pub fn sequence_flow(self, song: &super::renoise::Song) -> Vec<EntryFlow> {
+        match self {
+            Flow::Plain => vec![],
+            Flow::Annotated => super::flow::sequence_flow(song)
+        }
+    }
+}
 
-pub fn render(song: &super::renoise::Song, pattern: usize) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
-    let width: u32 = 2 + x_offset_upto_track(&song, song.tracks.len());
-    let height: u32 = 2 + song.pattern_pool.patterns[pattern].number_of_lines * (CHAR_WIDTH + TRACK_SPACING_Y);
+// The part of a pattern that gets rendered: a subset of its tracks (laid out
+// in the order they should appear) and a range of lines, and how its notes
+// are coloured, with the instruments to list in the legend below it and the
+// flow to annotate next to it, if any.
+struct Region {
+    layout: Layout,
+    lines: std::ops::Range<u32>,
+    note_colors: NoteColors,
+    legend: Vec<u32>,
+    flow: Option<EntryFlow>
+}
 
-    println!("image size: {}x{}", width, height);
-    let mut imgbuf = image::ImageBuffer::new(width, height);
+impl Region {
+    // Picks the given tracks and lines of a pattern, leaving out those that
+    // do not exist.
+    fn new(song: &super::renoise::Song, pattern: usize, tracks: &[usize], lines: std::ops::Range<u32>, note_colors: NoteColors) -> Region {
+        let pat = &song.pattern_pool.patterns[pattern];
+        let tracks: Vec<usize> = tracks.iter().cloned().filter(|&index| index < pat.tracks.len()).collect();
+        let lines = lines.start.min(pat.number_of_lines)..lines.end.min(pat.number_of_lines);
+
+        let layout = Layout::new(song, &tracks);
+        let legend = match note_colors {
+            NoteColors::Default => vec![],
+            NoteColors::Instrument => used_instruments(song, pattern, &layout, &lines)
+        };
 
-    render_pattern(&mut imgbuf, &song, pattern);
+        Region {
+            layout,
+            lines,
+            note_colors,
+            legend,
+            flow: None
+        }
+    }
 
-    imgbuf
-}
+    fn all(song: &super::renoise::Song, pattern: usize, note_colors: NoteColors) -> Region {
+        let pat = &song.pattern_pool.patterns[pattern];
+        let tracks: Vec<usize> = (0..pat.tracks.len()).collect();
 
-fn x_offset_for_track(song: &super::renoise::Song, track_index: usize) -> u32 {
-    let mut offset: u32 = 0;
+        Region::new(song, pattern, &tracks, 0..pat.number_of_lines, note_colors)
+    }
+
+    fn width(&self) -> u32 {
+        self.layout.width + if self.flow.is_some() { FLOW_MARGIN_WIDTH } else { 0 }
+    }
 
-    let track = &song.tracks[track_index];
-    offset += track.number_of_visible_note_columns * TRACK_WIDTH_NOTE;
-    if track.volume_column_is_visible {
-        offset += track.number_of_visible_note_columns * TRACK_WIDTH_VOL
+    // The height of the track headers and lines, without the legend.
+    fn grid_height(&self) -> u32 {
+        HEADER_HEIGHT + self.lines.len() as u32 * LINE_HEIGHT
     }
-    if track.panning_column_is_visible {
-        offset += track.number_of_visible_note_columns * TRACK_WIDTH_VOL
+
+    fn legend_entries_per_row(&self) -> u32 {
+        (self.layout.width / LEGEND_ENTRY_WIDTH).max(1)
     }
-    offset += track.number_of_visible_effect_columns * TRACK_WIDTH_FX;
-    offset += TRACK_SPACING_X;
 
-    offset
+    fn legend_height(&self) -> u32 {
+        if self.legend.is_empty() {
+            return 0;
+        }
+
+        let rows = (self.legend.len() as u32).div_ceil(self.legend_entries_per_row());
+        TRACK_SPACING_Y + rows * LINE_HEIGHT
+    }
+
+    fn height(&self) -> u32 {
+        self.grid_height() + self.legend_height()
+    }
 }
 
-fn x_offset_upto_track(song: &super::renoise::Song, track_index: usize) -> u32 {
-    (0..track_index).map(|i| x_offset_for_track(&song, i)).sum()
+// Returns the instruments played in the given lines of a pattern, in
+// ascending order.  Only the note columns in the layout count, so that the
+// legend leaves out notes in columns hidden in Renoise, which are not drawn.
+fn used_instruments(song: &super::renoise::Song, pattern: usize, layout: &Layout, lines: &std::ops::Range<u32>) -> Vec<u32> {
+    let mut instruments: Vec<u32> = vec![];
+    for track_layout in layout.tracks.iter() {
+        let note_columns: Vec<usize> = track_layout.columns.iter().filter(|column| column.kind == ColumnKind::Note).map(|column| column.index).collect();
+
+        for line in song.pattern_track_lines(pattern, track_layout.index).iter().filter(|line| lines.contains(&line.index)) {
+            instruments.extend(note_columns.iter()
+                .filter_map(|&index| line.note_columns.get(index).and_then(|n| n.as_ref()))
+                .filter_map(|note_column| note_column.instrument_number()));
+        }
+    }
+
+    instruments.sort_unstable();
+    instruments.dedup();
+    instruments
 }
 
-fn render_pattern(mut imgbuf: &mut image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, song: &super::renoise::Song, pattern: usize) {
+pub fn render(song: &super::renoise::Song, pattern: usize, note_colors: NoteColors) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
     let pat = &song.pattern_pool.patterns[pattern];
-    for (index, track) in pat.tracks.iter().enumerate() {
-        let x: u32 = x_offset_upto_track(&song, index);
-        let track_info = &song.tracks[index];
-        let lines: &Vec<super::renoise::Line> = if track.alias_pattern_index < 0 {
-            &track.lines
-        } else {
-            &song.pattern_pool.patterns[track.alias_pattern_index as usize].tracks[index].lines
-        };
+    let tracks: Vec<usize> = (0..pat.tracks.len()).collect();
 
-        let mut rendered_lines: Vec<u32> = vec![];
-
-        // // render highlighted background
-        // for line in (0..pat.number_of_lines).step_by(song.global_song_data.lines_per_beat as usize) {
-        //     for x in 0..imgbuf.width() {
-        //         for y in 0..CHAR_WIDTH {
-        //             let y_offset: u32 = line * (CHAR_WIDTH + TRACK_SPACING_Y);
-        //             imgbuf.put_pixel(x, y + y_offset, COLOR_BACK.highlighted);
-        //         }
-        //     }
-        // }
-
-        // render lines in track
-        for line in lines.iter() {
-            if line.index >= pat.number_of_lines {
-                continue;
-            }
-            rendered_lines.push(line.index);
+    render_region(song, pattern, &tracks, 0..pat.number_of_lines, note_colors)
+}
 
-            let y: u32 = line.index * (CHAR_WIDTH + TRACK_SPACING_Y);
-            let mut x_offset: u32 = 0;
-            let highlighted: bool = line.index % &song.global_song_data.lines_per_beat == 0;
+// Renders only the given tracks and lines of a pattern.  The tracks are laid
+// out next to each other without gaps, in the order they are given.
+pub fn render_region(song: &super::renoise::Song, pattern: usize, tracks: &[usize], lines: std::ops::Range<u32>, note_colors: NoteColors) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    let (width, height) = region_size(song, pattern, tracks, lines.clone(), note_colors);
 
-            for (i_note, note_column) in line.note_columns.iter().enumerate() {
-                if i_note as u32 >= track_info.number_of_visible_note_columns {
-                    break;
-                }
-                x_offset = render_note_column(&mut imgbuf, &note_column, &track_info, highlighted, x, x_offset, y);
-            }
+    let mut imgbuf = image::ImageBuffer::new(width, height);
 
-            if line.note_columns.len() < track_info.number_of_visible_note_columns as usize {
-                for _ in line.note_columns.len()..track_info.number_of_visible_note_columns as usize {
-                    x_offset = render_note_column(&mut imgbuf, &None, &track_info, highlighted, x, x_offset, y);
-                }
-            }
+    draw_region(&mut imgbuf, song, pattern, tracks, lines, note_colors);
 
-            for (i_fx, effect_column) in line.effect_columns.iter().enumerate() {
-                if i_fx as u32 >= track_info.number_of_visible_effect_columns {
-                    break;
-                }
+    imgbuf
+}
 
-                x_offset = render_effect_column(&mut imgbuf, &effect_column, highlighted, x, x_offset, y);
-            }
+// Returns the size of what `render_region` and `draw_region` draw.
+pub fn region_size(song: &super::renoise::Song, pattern: usize, tracks: &[usize], lines: std::ops::Range<u32>, note_colors: NoteColors) -> (u32, u32) {
+    let region = Region::new(song, pattern, tracks, lines, note_colors);
 
-            if line.effect_columns.len() < track_info.number_of_visible_effect_columns as usize {
-                for _ in line.effect_columns.len()..track_info.number_of_visible_effect_columns as usize {
-                    x_offset = render_effect_column(&mut imgbuf, &None, highlighted, x, x_offset, y);
-                }
-            }
-        }
+    (2 + region.width(), 2 + region.height())
+}
 
-        // render empty strings
-        for line in 0..pat.number_of_lines {
-            if rendered_lines.contains(&line) {
-                continue;
-            }
+// Draws the given tracks and lines of a pattern to any canvas, with the top
+// left corner of the pattern at the top left corner of the canvas.
+pub fn draw_region<C: Canvas>(canvas: &mut C, song: &super::renoise::Song, pattern: usize, tracks: &[usize], lines: std::ops::Range<u32>, note_colors: NoteColors) {
+    let region = Region::new(song, pattern, tracks, lines, note_colors);
 
-            let y: u32 = line as u32 * (CHAR_WIDTH + TRACK_SPACING_Y);
-            let mut x_offset: u32 = 0;
-            let highlighted: bool = line % &song.global_song_data.lines_per_beat == 0;
+    render_pattern(canvas, song, pattern, &region, 0, &[], MuteStyle::Dim);
+}
 
-            for _ in 0..track_info.number_of_visible_note_columns {
-                x_offset = render_note_column(&mut imgbuf, &None, &track_info, highlighted, x, x_offset, y);
-            }
-            for _ in 0..track_info.number_of_visible_effect_columns {
-                x_offset = render_effect_column(&mut imgbuf, &None, highlighted, x, x_offset, y);
-            }
+// Renders the pattern played at the given position of the pattern sequence
+// "as heard", i.e. with the tracks muted in that slot dimmed or hidden.
+pub fn render_sequence_entry(song: &super::renoise::Song, sequence_index: usize, mute_style: MuteStyle, note_colors: NoteColors, flow: Option<&EntryFlow>) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    let region = Region::all(song, song.pattern_sequence.sequence_entries[sequence_index].pattern as usize, note_colors);
+    let width: u32 = 2 + region.width() + if flow.is_some() { FLOW_MARGIN_WIDTH } else { 0 };
+    let height: u32 = 2 + region.height();
+
+    let mut imgbuf = image::ImageBuffer::new(width, height);
+
+    draw_sequence_entry(&mut imgbuf, song, sequence_index, region.lines, mute_style, note_colors, flow);
+
+    imgbuf
+}
+
+// Like `render_sequence_entry`, but draws only the given lines, to any
+// canvas.  `region_size` with all tracks of the pattern tells the size,
+// without the margin of the flow.  `flow` is the flow of the sequence position
+// to annotate, if any, as `Flow::sequence_flow` returns it.
+pub fn draw_sequence_entry<C: Canvas>(canvas: &mut C, song: &super::renoise::Song, sequence_index: usize, lines: std::ops::Range<u32>, mute_style: MuteStyle, note_colors: NoteColors, flow: Option<&EntryFlow>) {
+    let entry = &song.pattern_sequence.sequence_entries[sequence_index];
+    let pattern = entry.pattern as usize;
+    let tracks: Vec<usize> = (0..song.pattern_pool.patterns[pattern].tracks.len()).collect();
+    let mut region = Region::new(song, pattern, &tracks, lines, note_colors);
+    region.flow = flow.cloned();
+
+    render_pattern(canvas, song, entry.pattern as usize, &region, 0, &entry.muted_tracks, mute_style);
+}
+
+// Renders the patterns in the order of the pattern sequence, each of them
+// preceded by a separator naming the sequence position and section.  If
+// `max_height` is given, the song is split into several pages at pattern
+// boundaries.
+pub fn render_song(song: &super::renoise::Song, max_height: Option<u32>, mute_style: MuteStyle, note_colors: NoteColors, flow: Flow) -> Vec<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>> {
+    let entries = &song.pattern_sequence.sequence_entries;
+    let width: u32 = 2 + Layout::for_song(song).width + flow.margin_width();
+    let mut regions: Vec<Region> = entries.iter().map(|entry| Region::all(song, entry.pattern as usize, note_colors)).collect();
+    for (region, entry_flow) in regions.iter_mut().zip(flow.sequence_flow(song)) {
+        region.flow = Some(entry_flow);
+    }
+
+    let mut pages: Vec<Vec<usize>> = vec![];
+    let mut page_height: u32 = 0;
+    for (index, region) in regions.iter().enumerate() {
+        let height = SEPARATOR_HEIGHT + region.height();
+        if pages.is_empty() || max_height.is_some_and(|max| page_height + height > max) {
+            pages.push(vec![]);
+            page_height = 0;
         }
+        pages.last_mut().unwrap().push(index);
+        page_height += height;
     }
+
+    pages.iter().map(|page| {
+        let height: u32 = 2 + page.iter().map(|&index| SEPARATOR_HEIGHT + regions[index].height()).sum::<u32>();
+
+        let mut imgbuf = image::ImageBuffer::new(width, height);
+
+        let mut y: u32 = 0;
+        for &index in page.iter() {
+            let entry = &entries[index];
+            render_separator(&mut imgbuf, index, entry, y);
+            y += SEPARATOR_HEIGHT;
+
+            render_pattern(&mut imgbuf, song, entry.pattern as usize, &regions[index], y, &entry.muted_tracks, mute_style);
+            y += regions[index].height();
+        }
+
+        imgbuf
+    }).collect()
 }
 
-fn render_note_column(mut imgbuf: &mut image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, note_column: &Option<super::renoise::NoteColumn>, track_info: &super::renoise::Track, highlighted: bool, x: u32, x_offset: u32, y: u32) -> u32 {
-    let empty_str_note = &String::from("   ");
-    let empty_str_vol = &String::from("..");
+fn render_separator<C: Canvas>(canvas: &mut C, sequence_index: usize, entry: &super::renoise::SequenceEntry, y: u32) {
+    let text = format!("{:03} PATTERN {:02} {}", sequence_index, entry.pattern, entry.section_name.to_uppercase());
+    let width: u32 = canvas.width();
+    render_label(canvas, &text, 0, y + TRACK_SPACING_Y, width, &COLOR_DEFAULT.highlighted);
 
-    let mut x_offset = x_offset;
-    match note_column {
-        Some(note) => {
-            render_text(&mut imgbuf, &note.note, x + x_offset, y, &COLOR_DEFAULT.get(highlighted));
-            render_text(&mut imgbuf, &note.instrument, x + x_offset + (CHAR_WIDTH * 3), y, &COLOR_DEFAULT.get(highlighted));
-            x_offset += TRACK_WIDTH_NOTE;
+    let line_y: u32 = y + SEPARATOR_HEIGHT - 2 * TRACK_SPACING_Y;
+    canvas.draw_line(0, line_y, width - 1, line_y, &COLOR_DEFAULT.normal);
+}
 
-            if track_info.volume_column_is_visible {
-                render_text(&mut imgbuf, &note.volume, x + x_offset, y, &COLOR_VOLUME.get(highlighted));
-                x_offset += TRACK_WIDTH_VOL;
-            }
+// Renders the track name and colour above a track.  Aliased tracks are
+// labelled with the pattern they are an alias of instead.
+fn render_header<C: Canvas>(canvas: &mut C, song: &super::renoise::Song, pattern: usize, track_layout: &TrackLayout, y: u32) {
+    let (track_index, x, width) = (track_layout.index, track_layout.x, track_layout.width);
+    let track_info = &song.tracks[track_index];
+    let alias_pattern_index = song.pattern_pool.patterns[pattern].tracks[track_index].alias_pattern_index;
+
+    if alias_pattern_index >= 0 {
+        let text = match song.resolve_alias(pattern, track_index) {
+            Ok(_) => format!("→ PATTERN {:02}", alias_pattern_index),
+            Err(_) => format!("→ BAD ALIAS {:02}", alias_pattern_index)
+        };
+        render_label(canvas, &text, x, y, width, &COLOR_ALIAS.normal);
+    } else {
+        render_label(canvas, &track_info.name.to_uppercase(), x, y, width, &COLOR_DEFAULT.normal);
+    }
 
-            if track_info.panning_column_is_visible {
-                render_text(&mut imgbuf, &note.panning, x + x_offset, y, &COLOR_PANNING.get(highlighted));
-                x_offset += TRACK_WIDTH_VOL;
-            }
-        },
-        None => {
-            render_text(&mut imgbuf, &empty_str_note, x + x_offset, y, &COLOR_DEFAULT.get(highlighted));
-            render_text(&mut imgbuf, &empty_str_vol, x + x_offset + (CHAR_WIDTH * 3), y, &COLOR_DEFAULT.get(highlighted));
-            x_offset += TRACK_WIDTH_NOTE;
-
-            if track_info.volume_column_is_visible {
-                render_text(&mut imgbuf, &empty_str_vol, x + x_offset, y, &COLOR_VOLUME.get(highlighted));
-                x_offset += TRACK_WIDTH_VOL;
+    let [r, g, b] = track_info.rgb();
+    canvas.fill_rect(x, y + LINE_HEIGHT, width, 2, &image::Rgba([r, g, b, 255]));
+}
+
+fn render_pattern<C: Canvas>(canvas: &mut C, song: &super::renoise::Song, pattern: usize, region: &Region, y_offset: u32, muted_tracks: &[u32], mute_style: MuteStyle) {
+    let pat = &song.pattern_pool.patterns[pattern];
+
+    // the tracks to draw, with their lines looked up by line number
+    let tracks: Vec<(&TrackLayout, Vec<Option<&super::renoise::Line>>)> = region.layout.tracks.iter()
+        .filter(|track_layout| mute_style != MuteStyle::Hide || !muted_tracks.contains(&(track_layout.index as u32)))
+        .map(|track_layout| {
+            let mut lines: Vec<Option<&super::renoise::Line>> = vec![None; region.lines.len()];
+            for line in song.pattern_track_lines(pattern, track_layout.index).iter() {
+                if line.index < pat.number_of_lines && region.lines.contains(&line.index) {
+                    lines[(line.index - region.lines.start) as usize] = Some(line);
+                }
             }
+            (track_layout, lines)
+        })
+        .collect();
+
+    for (track_layout, _) in tracks.iter() {
+        render_header(canvas, song, pattern, track_layout, y_offset);
+    }
 
-            if track_info.panning_column_is_visible {
-                render_text(&mut imgbuf, &empty_str_vol, x + x_offset, y, &COLOR_PANNING.get(highlighted));
-                x_offset += TRACK_WIDTH_VOL;
+    // draw line by line across all tracks rather than track by track, which
+    // keeps the writes to the image close together, stamping the empty cells
+    // of each line, which are most of a pattern, in one go
+    let mut empty_cells = EmptyCells::default();
+    for (position, line_index) in region.lines.clone().enumerate() {
+        let y: u32 = y_offset + HEADER_HEIGHT + position as u32 * LINE_HEIGHT;
+        let highlighted: bool = line_index % song.global_song_data.lines_per_beat == 0;
+
+        for (track_layout, lines) in tracks.iter() {
+            // tint the background of aliased tracks
+            if pat.tracks[track_layout.index].alias_pattern_index >= 0 {
+                canvas.fill_rect(track_layout.x, y, track_layout.width, LINE_HEIGHT, &COLOR_ALIAS_BACK.get(highlighted));
             }
+            render_line(canvas, lines[position], track_layout, highlighted, region.note_colors, &mut empty_cells, y);
+        }
+        empty_cells.draw(canvas);
+    }
+
+    // fade out the tracks muted in the current sequence slot
+    for (track_layout, _) in tracks.iter() {
+        if muted_tracks.contains(&(track_layout.index as u32)) {
+            canvas.fade_rect(track_layout.x, y_offset, track_layout.width + TRACK_SPACING_X, region.grid_height(), MUTED_ALPHA);
         }
     }
-    x_offset
+
+    if let Some(flow) = &region.flow {
+        render_flow(canvas, song, region, flow, y_offset);
+    }
+
+    render_legend(canvas, region, y_offset + region.grid_height() + TRACK_SPACING_Y);
 }
 
-fn render_effect_column(mut imgbuf: &mut image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, effect_column: &Option<super::renoise::EffectColumn>, highlighted: bool, x: u32, x_offset: u32, y: u32) -> u32 {
-    let empty_str_fx = &String::from("  ");
+// Greys out the lines of the region that are never played and labels the
+// lines where the flow changes in the margin: breaks with the line they jump
+// to, the line entered after a break in the previous pattern, and delays
+// with their number of lines.  A rule below a break and above the line
+// entered marks where playback leaves and enters the pattern.
+fn render_flow<C: Canvas>(canvas: &mut C, song: &super::renoise::Song, region: &Region, flow: &EntryFlow, y_offset: u32) {
+    let (grid_width, margin_x) = (region.layout.width, region.layout.width);
+
+    for (position, line_index) in region.lines.clone().enumerate() {
+        let y: u32 = y_offset + HEADER_HEIGHT + position as u32 * LINE_HEIGHT;
+        let color = COLOR_GLOBAL_FX.get(line_index % song.global_song_data.lines_per_beat == 0);
+
+        if !flow.is_played(line_index) {
+            canvas.fade_rect(0, y, grid_width, LINE_HEIGHT, UNPLAYED_ALPHA);
+        }
 
-    let mut x_offset = x_offset;
-    match effect_column {
-        Some(effect) => {
-            let color = fx_color(&effect.number);
-            render_text(&mut imgbuf, &fx_command(&effect.number), x + x_offset, y, &color.get(highlighted));
-            render_text(&mut imgbuf, &effect.value, x + x_offset + (CHAR_WIDTH * 2), y, &color.get(highlighted));
-            x_offset += TRACK_WIDTH_FX;
-        },
-        None => {
-            render_text(&mut imgbuf, &empty_str_fx, x + x_offset, y, &COLOR_DEFAULT.get(highlighted));
-            render_text(&mut imgbuf, &empty_str_fx, x + x_offset + (CHAR_WIDTH * 2), y, &COLOR_DEFAULT.get(highlighted));
-            x_offset += TRACK_WIDTH_FX;
+        let label = match flow.pattern_break {
+            Some((break_line, target)) if break_line == line_index => {
+                canvas.draw_line(0, y + LINE_HEIGHT - 1, grid_width - 1, y + LINE_HEIGHT - 1, &COLOR_GLOBAL_FX.normal);
+                Some(format!("→{:02X}", target))
+            },
+            _ if line_index == flow.first_line && line_index > 0 => {
+                canvas.draw_line(0, y, grid_width - 1, y, &COLOR_GLOBAL_FX.normal);
+                Some(String::from("←ZB"))
+            },
+            _ => flow.delays.iter().find(|&&(line, _)| line == line_index).map(|&(_, lines)| format!("+{:02X}", lines))
+        };
+        if let Some(label) = label {
+            render_label(canvas, &label, margin_x, y, FLOW_MARGIN_WIDTH, &color);
         }
     }
-    x_offset
 }
 
-fn render_text(mut imgbuf: &mut image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, text: &String, x: u32, y: u32, color: &image::Rgba<u8>) {
-    let rendered_chars = text.as_bytes().iter().map(|b| super::pattern_font::char(*b));
+// Renders the instruments of the region as colour swatches followed by their
+// numbers, wrapping into as many rows as needed.
+fn render_legend<C: Canvas>(canvas: &mut C, region: &Region, y: u32) {
+    let per_row = region.legend_entries_per_row() as usize;
+
+    for (index, &instrument) in region.legend.iter().enumerate() {
+        let x: u32 = (index % per_row) as u32 * LEGEND_ENTRY_WIDTH;
+        let entry_y: u32 = y + (index / per_row) as u32 * LINE_HEIGHT;
+        let color = instrument_color(instrument);
 
-    for (index, ch) in rendered_chars.enumerate() {
-        render_char(&mut imgbuf, ch, x + 8 * index as u32, y, &color);
+        canvas.fill_rect(x, entry_y, CHAR_WIDTH - 1, CHAR_WIDTH - 1, &color);
+        render_text(canvas, &format!("{:02X}", instrument), x + 2 * CHAR_WIDTH, entry_y, &color);
     }
 }
 
-fn render_char(imgbuf: &mut image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, ch: [u8; 8], x: u32, y: u32, color: &image::Rgba<u8>) {
-    for (index, row) in ch.iter().enumerate() {
-        let real_y: u32 = y + index as u32;
+// The empty cells of a pattern by column kind and highlighting, collected
+// while its lines are drawn so that their dots can be stamped all at once.
+#[derive(Default)]
+struct EmptyCells {
+    positions: [Vec<(u32, u32)>; 8]
+}
+
+impl EmptyCells {
+    const KINDS: [ColumnKind; 4] = [ColumnKind::Note, ColumnKind::Volume, ColumnKind::Panning, ColumnKind::Effect];
 
-        let mut num = *row;
-        let mut x_offset = 0;
-        while num != 0 {
-            if num >> 7 == 1 {
-                imgbuf.put_pixel(x + x_offset, real_y, *color);
+    fn add(&mut self, kind: ColumnKind, highlighted: bool, x: u32, y: u32) {
+        let index = EmptyCells::KINDS.iter().position(|&k| k == kind).unwrap_or(0);
+        self.positions[2 * index + highlighted as usize].push((x, y));
+    }
+
+    // Draws the cells added so far, and forgets them.
+    fn draw<C: Canvas>(&mut self, canvas: &mut C) {
+        for (index, &kind) in EmptyCells::KINDS.iter().enumerate() {
+            for &highlighted in [false, true].iter() {
+                let (text, color) = match kind {
+                    ColumnKind::Note => ("   ..", COLOR_DEFAULT.get(highlighted)),
+                    ColumnKind::Volume => ("..", COLOR_VOLUME.get(highlighted)),
+                    ColumnKind::Panning => ("..", COLOR_PANNING.get(highlighted)),
+                    ColumnKind::Effect => ("    ", COLOR_DEFAULT.get(highlighted))
+                };
+                let positions = &mut self.positions[2 * index + highlighted as usize];
+                if !positions.is_empty() {
+                    canvas.stamp_text(text.as_bytes(), positions, &color);
+                    positions.clear();
+                }
             }
-            x_offset += 1;
-            num <<= 1;
         }
     }
 }
 
-fn fx_color(number: &String) -> &ColorPair {
-    match number.as_bytes() {
-        [b'Z', b'T'] | [b'Z', b'L'] | [b'Z', b'K'] | [b'Z', b'G'] | [b'Z', b'B'] | [b'Z', b'D'] => &COLOR_GLOBAL_FX,
-        [_, b'A'] | [_, b'U'] | [_, b'D'] | [_, b'G'] | [_, b'V'] => &COLOR_PITCH,
-        [_, b'I'] | [_, b'O'] | [_, b'T'] | [_, b'C'] | [_, b'M'] | [_, b'L'] => &COLOR_VOLUME,
-        [_, b'S'] | [_, b'B'] | [_, b'E'] | [_, b'Q'] | [_, b'R'] | [_, b'Y'] => &COLOR_DELAY,
-        [_, b'N'] | [_, b'P'] | [_, b'W'] => &COLOR_PANNING,
-        [_, b'X'] | [_, b'Z'] | [_, b'J'] => &COLOR_OTHER_FX,
-        _ => &COLOR_UNUSED_FX,
+// Renders a line of a track column by column, leaving the dots of empty
+// columns to `empty_cells`.
+fn render_line<C: Canvas>(canvas: &mut C, line: Option<&super::renoise::Line>, track_layout: &TrackLayout, highlighted: bool, note_colors: NoteColors, empty_cells: &mut EmptyCells, y: u32) {
+    for column in track_layout.columns.iter() {
+        let x = column.x;
+        let note_column = line.and_then(|l| l.note_columns.get(column.index)).and_then(|n| n.as_ref());
+        let effect_column = line.and_then(|l| l.effect_columns.get(column.index)).and_then(|e| e.as_ref());
+
+        match (column.kind, note_column, effect_column) {
+            (ColumnKind::Note, Some(note), _) => {
+                let color = match (note_colors, note.instrument_number()) {
+                    (NoteColors::Instrument, Some(instrument)) => instrument_color(instrument),
+                    _ => COLOR_DEFAULT.get(highlighted)
+                };
+                render_text(canvas, &note.note, x, y, &color);
+                render_text(canvas, &note.instrument, x + CHAR_WIDTH * 3, y, &color);
+            },
+            (ColumnKind::Note, None, _) => empty_cells.add(ColumnKind::Note, highlighted, x, y),
+            (ColumnKind::Volume, Some(note), _) => render_text(canvas, &note.volume, x, y, &COLOR_VOLUME.get(highlighted)),
+            (ColumnKind::Volume, None, _) => empty_cells.add(ColumnKind::Volume, highlighted, x, y),
+            (ColumnKind::Panning, Some(note), _) => render_text(canvas, &note.panning, x, y, &COLOR_PANNING.get(highlighted)),
+            (ColumnKind::Panning, None, _) => empty_cells.add(ColumnKind::Panning, highlighted, x, y),
+            (ColumnKind::Effect, _, Some(effect)) => {
+                let color = fx_color(&effect.number);
+                render_text(canvas, &fx_command(&effect.number), x, y, &color.get(highlighted));
+                render_text(canvas, &effect.value, x + CHAR_WIDTH * 2, y, &color.get(highlighted));
+            },
+            (ColumnKind::Effect, _, None) => empty_cells.add(ColumnKind::Effect, highlighted, x, y)
+        }
     }
 }
 
-fn fx_command(number: &String) -> String {
-    if number.as_bytes()[0] == b'0' {
-        String::from_utf8(vec!(b' ', number.as_bytes()[1])).unwrap()
-    } else {
-        number.to_string()
+fn render_text<C: Canvas>(canvas: &mut C, text: &str, x: u32, y: u32, color: &image::Rgba<u8>) {
+    canvas.draw_text(text.as_bytes(), x, y, color);
+}
+
+// Renders text outside of the pattern grid, such as track names.  Unlike
+// `render_text`, spaces are left blank, and the text is cut off at
+// `max_width`.
+pub fn render_label<C: Canvas>(canvas: &mut C, text: &str, x: u32, y: u32, max_width: u32, color: &image::Rgba<u8>) {
+    let max_chars = (max_width / CHAR_WIDTH) as usize;
+
+    for (index, c) in text.chars().take(max_chars).enumerate() {
+        if c != ' ' {
+            canvas.draw_glyph(super::pattern_font::code(c), x + CHAR_WIDTH * index as u32, y, color);
+        }
     }
 }
@@ -3,7 +3,7 @@ extern crate zip;
 
 use std::io::Read;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Song {
     pub global_song_data: GlobalSongData,
     pub tracks: Vec<Track>,
@@ -17,20 +17,57 @@ impl Song {
             Ok(doc) => doc,
             Err(e) => { bail!(e); }
         };
+        if !doc.root_element().has_tag_name("RenoiseSong") {
+            bail!("Not a valid Renoise song");
+        }
+
+        let song = Song {
+            global_song_data: make_global_song_data(&doc)?,
+            tracks: collect_tracks(&doc)?,
+            pattern_pool: make_pattern_pool(&doc)?,
+            pattern_sequence: make_pattern_sequence(&doc)?
+        };
+        song.validate()?;
 
-        Ok(
-            Song {
-                global_song_data: make_global_song_data(&doc),
-                tracks: collect_tracks(&doc),
-                pattern_pool: make_pattern_pool(&doc),
-                pattern_sequence: make_pattern_sequence(&doc)
+        Ok(song)
+    }
+
+    // Checks that the tempo is not zero, that the patterns have a track for
+    // each track of the song and that the pattern sequence and aliases only
+    // refer to patterns of the pool, so that the renderers can use them
+    // without checking.
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let global_song_data = &self.global_song_data;
+        for (name, value) in [("BeatsPerMin", global_song_data.beats_per_min), ("LinesPerBeat", global_song_data.lines_per_beat), ("TicksPerLine", global_song_data.ticks_per_line)].iter() {
+            if *value == 0 {
+                bail!("<{}> of <GlobalSongData> is 0", name);
             }
-        )
+        }
+
+        let patterns = &self.pattern_pool.patterns;
+
+        for (index, pattern) in patterns.iter().enumerate() {
+            if pattern.tracks.len() != self.tracks.len() {
+                bail!("Pattern {} has {} tracks instead of {}", index, pattern.tracks.len(), self.tracks.len());
+            }
+            for (track, pattern_track) in pattern.tracks.iter().enumerate() {
+                if pattern_track.alias_pattern_index >= patterns.len() as i32 {
+                    bail!("Track {} of pattern {} is an alias of pattern {}, which does not exist", track, index, pattern_track.alias_pattern_index);
+                }
+            }
+        }
+        for (index, entry) in self.pattern_sequence.sequence_entries.iter().enumerate() {
+            if entry.pattern as usize >= patterns.len() {
+                bail!("Sequence position {} plays pattern {}, which does not exist", index, entry.pattern);
+            }
+        }
+
+        Ok(())
     }
 
     pub fn from_xrns(xrns: &std::path::Path) -> Result<Song, Box<dyn std::error::Error>> {
-        let song_file = std::fs::File::open(&xrns).unwrap();
-        let mut archive = zip::ZipArchive::new(song_file).unwrap();
+        let song_file = std::fs::File::open(xrns)?;
+        let mut archive = zip::ZipArchive::new(song_file)?;
 
         let mut file = match archive.by_name("Song.xml") {
             Ok(file) => file,
@@ -38,13 +75,47 @@ impl Song {
         };
 
         let mut data = String::new();
-        file.read_to_string(&mut data).unwrap();
+        file.read_to_string(&mut data)?;
 
         Song::from_xml(data)
     }
+
+    // Follows the alias of a pattern track, and the aliases of the pattern
+    // tracks it points to, and returns the index of the pattern that actually
+    // holds the lines.
+    pub fn resolve_alias(&self, pattern: usize, track: usize) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut visited: Vec<usize> = vec![];
+        let mut current = pattern;
+
+        loop {
+            let pattern_track = match self.pattern_pool.patterns.get(current).and_then(|p| p.tracks.get(track)) {
+                Some(pattern_track) => pattern_track,
+                None => { bail!("Pattern {} has no track {}", current, track); }
+            };
+
+            if pattern_track.alias_pattern_index < 0 {
+                return Ok(current);
+            }
+
+            visited.push(current);
+            current = pattern_track.alias_pattern_index as usize;
+            if visited.contains(&current) {
+                bail!("Track {} of pattern {} is part of an alias cycle", track, pattern);
+            }
+        }
+    }
+
+    // Returns the lines of a pattern track, following aliases.  Tracks with
+    // broken aliases are treated as empty.
+    pub fn pattern_track_lines(&self, pattern: usize, track: usize) -> &[Line] {
+        match self.resolve_alias(pattern, track) {
+            Ok(resolved) => &self.pattern_pool.patterns[resolved].tracks[track].lines,
+            Err(_) => &[]
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct GlobalSongData {
     pub beats_per_min: u32,
     pub lines_per_beat: u32,
@@ -54,8 +125,9 @@ pub struct GlobalSongData {
     pub artist: String
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Track {
+    pub r#type: String,
     pub name: String,
     pub color: String,
     pub state: String,
@@ -67,18 +139,29 @@ pub struct Track {
     pub delay_column_is_visible: bool,
 }
 
-#[derive(Debug)]
+impl Track {
+    pub fn rgb(&self) -> [u8; 3] {
+        let mut rgb = [0; 3];
+        for (i, component) in self.color.split(',').take(3).enumerate() {
+            rgb[i] = component.trim().parse().unwrap_or(0);
+        }
+        rgb
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct PatternPool {
     pub patterns: Vec<Pattern>
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Pattern {
+    pub name: String,
     pub number_of_lines: u32,
     pub tracks: Vec<PatternTrack>
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct PatternTrack {
     pub r#type: String,
     pub alias_pattern_index: i32,
@@ -86,7 +169,7 @@ pub struct PatternTrack {
     pub lines: Vec<Line>
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Line {
     pub index: u32,
 
@@ -94,7 +177,7 @@ pub struct Line {
     pub effect_columns: Vec<Option<EffectColumn>>
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct NoteColumn {
     pub note: String,
     pub instrument: String,
@@ -102,18 +185,94 @@ pub struct NoteColumn {
     pub panning: String
 }
 
-#[derive(Debug)]
+impl NoteColumn {
+    // Returns the note as a number of semitones above C-0, or `None` for
+    // empty columns and note-offs.
+    pub fn pitch(&self) -> Option<u32> {
+        let bytes = self.note.as_bytes();
+        if bytes.len() != 3 || !bytes[2].is_ascii_digit() {
+            return None;
+        }
+
+        let semitone: u32 = match bytes[0] {
+            b'C' => 0,
+            b'D' => 2,
+            b'E' => 4,
+            b'F' => 5,
+            b'G' => 7,
+            b'A' => 9,
+            b'B' => 11,
+            _ => { return None; }
+        };
+        let sharp: u32 = if bytes[1] == b'#' { 1 } else { 0 };
+        let octave: u32 = (bytes[2] - b'0') as u32;
+
+        Some(octave * 12 + semitone + sharp)
+    }
+
+    pub fn is_note_off(&self) -> bool {
+        self.note == "OFF"
+    }
+
+    pub fn instrument_number(&self) -> Option<u32> {
+        u32::from_str_radix(&self.instrument, 16).ok()
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct EffectColumn {
     pub value: String,
     pub number: String
 }
 
-#[derive(Debug)]
+impl EffectColumn {
+    pub fn description(&self) -> String {
+        let value = u8::from_str_radix(&self.value, 16).unwrap_or(0);
+        let (x, y) = (value >> 4, value & 0x0f);
+
+        match self.number.as_bytes() {
+            [b'Z', b'T'] => format!("Set tempo to {} BPM", value),
+            [b'Z', b'L'] => format!("Set lines per beat to {}", value),
+            [b'Z', b'K'] => format!("Set ticks per line to {}", value),
+            [b'Z', b'G'] => format!("{} groove", if value == 0 { "Disable" } else { "Enable" }),
+            [b'Z', b'B'] => format!("Break pattern and jump to line {:02X} of the next pattern", value),
+            [b'Z', b'D'] => format!("Delay pattern for {} lines", value),
+            [b'0', b'A'] => format!("Arpeggio (+{} and +{} semitones)", x, y),
+            [b'0', b'U'] => format!("Slide pitch up by {} 1/16 semitones", value),
+            [b'0', b'D'] => format!("Slide pitch down by {} 1/16 semitones", value),
+            [b'0', b'G'] => format!("Glide towards note by {} 1/16 semitones", value),
+            [b'0', b'V'] => format!("Vibrato (speed {}, depth {})", x, y),
+            [b'0', b'I'] => format!("Fade volume in by {}", value),
+            [b'0', b'O'] => format!("Fade volume out by {}", value),
+            [b'0', b'T'] => format!("Tremolo (speed {}, depth {})", x, y),
+            [b'0', b'C'] => format!("Cut volume to {} after {} ticks", x, y),
+            [b'0', b'M'] => format!("Set channel volume to {:02X}", value),
+            [b'0', b'L'] => format!("Set track volume to {:02X}", value),
+            [b'0', b'S'] => format!("Trigger sample slice or offset {:02X}", value),
+            [b'0', b'B'] => format!("Play sample {}", if value == 0 { "backwards" } else { "forwards" }),
+            [b'0', b'E'] => format!("Set envelope position to {}", value),
+            [b'0', b'Q'] => format!("Delay notes by {} ticks", value),
+            [b'0', b'R'] => format!("Retrigger every {} ticks with volume {}", y, x),
+            [b'0', b'Y'] => format!("Trigger line with probability {:02X}", value),
+            [b'0', b'N'] => format!("Auto pan (speed {}, depth {})", x, y),
+            [b'0', b'P'] => format!("Set track panning to {:02X}", value),
+            [b'0', b'W'] => format!("Set track surround width to {:02X}", value),
+            [b'0', b'X'] => if value == 0 { String::from("Stop all notes and effects") } else { format!("Stop effect {:02X}", value) },
+            [b'0', b'Z'] => format!("Trigger phrase {:02X}", value),
+            [b'0', b'J'] => format!("Set track routing to {:02X}", value),
+            [device, parameter] if device.is_ascii_hexdigit() && parameter.is_ascii_hexdigit() =>
+                format!("Set parameter {} of device {} to {:02X}", *parameter as char, *device as char, value),
+            _ => format!("Unknown effect {}{}", self.number, self.value)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct PatternSequence {
     pub sequence_entries: Vec<SequenceEntry>
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct SequenceEntry {
     pub pattern: u32,
     pub section_name: String,
@@ -125,96 +284,117 @@ macro_rules! find_tag {
         $doc.children().find(|n| n.has_tag_name($name))
     }
 }
+// The text of a child element, which must be there but may be empty, or
+// `$default` if the element is missing or empty.
 macro_rules! find_tag_text {
     ($doc:expr, $name:expr) => {
-        find_tag!($doc, $name).unwrap().text()
-    };
-    ($doc:expr, $name:expr, $default:expr) => {
         match find_tag!($doc, $name) {
-            Some(n) => { n.text() },
-            None => { Some($default) }
+            Some(n) => { n.text().unwrap_or("") },
+            None => { bail!("<{}> has no <{}>", $doc.tag_name().name(), $name); }
         }
+    };
+    ($doc:expr, $name:expr, $default:expr) => {
+        find_tag!($doc, $name).and_then(|n| n.text()).unwrap_or($default)
     }
 }
 macro_rules! find_tag_text_parsed {
     ($doc:expr, $name:expr) => {
-        find_tag_text!($doc, $name).unwrap().parse()
+        match find_tag_text!($doc, $name).trim().parse() {
+            Ok(value) => { value },
+            Err(_) => { bail!("<{}> of <{}> is not valid: {:?}", $name, $doc.tag_name().name(), find_tag_text!($doc, $name)); }
+        }
+    }
+}
+macro_rules! require_tag {
+    ($doc:expr, $name:expr) => {
+        match find_tag!($doc, $name) {
+            Some(n) => { n },
+            None => { bail!("<{}> has no <{}>", $doc.tag_name().name(), $name); }
+        }
     }
 }
 
-fn make_global_song_data(doc: &roxmltree::Document) -> GlobalSongData {
-    let gsd = find_tag!(doc.root_element(), "GlobalSongData").unwrap();
+type ParseResult<T> = Result<T, Box<dyn std::error::Error>>;
 
-    GlobalSongData {
-        beats_per_min: find_tag_text_parsed!(gsd, "BeatsPerMin").unwrap(),
-        lines_per_beat: find_tag_text_parsed!(gsd, "LinesPerBeat").unwrap(),
-        ticks_per_line: find_tag_text_parsed!(gsd, "TicksPerLine").unwrap(),
+fn make_global_song_data(doc: &roxmltree::Document) -> ParseResult<GlobalSongData> {
+    let gsd = require_tag!(doc.root_element(), "GlobalSongData");
 
-        song_name: find_tag_text!(gsd, "SongName").unwrap().to_string(),
-        artist: find_tag_text!(gsd, "Artist").unwrap().to_string()
-    }
-}
+    Ok(GlobalSongData {
+        beats_per_min: find_tag_text_parsed!(gsd, "BeatsPerMin"),
+        lines_per_beat: find_tag_text_parsed!(gsd, "LinesPerBeat"),
+        ticks_per_line: find_tag_text_parsed!(gsd, "TicksPerLine"),
 
-fn collect_tracks(doc: &roxmltree::Document) -> Vec<Track> {
-    let tracks = find_tag!(doc.root_element(), "Tracks").unwrap();
+        song_name: find_tag_text!(gsd, "SongName", "").to_string(),
+        artist: find_tag_text!(gsd, "Artist", "").to_string()
+    })
+}
 
-    tracks.children().filter(|n| n.is_element()).map(|n|
-        Track {
-            name: find_tag_text!(n, "Name").unwrap().to_string(),
-            color: find_tag_text!(n, "Color").unwrap().to_string(),
-            state: find_tag_text!(n, "State").unwrap().to_string(),
-            number_of_visible_note_columns: find_tag_text_parsed!(n, "NumberOfVisibleNoteColumns").unwrap(),
-            number_of_visible_effect_columns: find_tag_text_parsed!(n, "NumberOfVisibleEffectColumns").unwrap(),
-            volume_column_is_visible: find_tag_text_parsed!(n, "VolumeColumnIsVisible").unwrap(),
-            panning_column_is_visible: find_tag_text_parsed!(n, "PanningColumnIsVisible").unwrap(),
-            delay_column_is_visible: find_tag_text_parsed!(n, "DelayColumnIsVisible").unwrap()
-        }
-    ).collect()
+fn collect_tracks(doc: &roxmltree::Document) -> ParseResult<Vec<Track>> {
+    let tracks = require_tag!(doc.root_element(), "Tracks");
+
+    tracks.children().filter(|n| n.is_element()).map(|n| -> ParseResult<Track> {
+        Ok(Track {
+            r#type: n.attribute("type").unwrap_or(n.tag_name().name()).to_string(),
+            name: find_tag_text!(n, "Name", "").to_string(),
+            color: find_tag_text!(n, "Color", "").to_string(),
+            state: find_tag_text!(n, "State", "Active").to_string(),
+            number_of_visible_note_columns: find_tag_text_parsed!(n, "NumberOfVisibleNoteColumns"),
+            number_of_visible_effect_columns: find_tag_text_parsed!(n, "NumberOfVisibleEffectColumns"),
+            volume_column_is_visible: find_tag_text_parsed!(n, "VolumeColumnIsVisible"),
+            panning_column_is_visible: find_tag_text_parsed!(n, "PanningColumnIsVisible"),
+            delay_column_is_visible: find_tag_text_parsed!(n, "DelayColumnIsVisible")
+        })
+    }).collect()
 }
 
-fn make_pattern_pool(doc: &roxmltree::Document) -> PatternPool {
-    let pattern_pool = find_tag!(doc.root_element(), "PatternPool").unwrap();
+fn make_pattern_pool(doc: &roxmltree::Document) -> ParseResult<PatternPool> {
+    let pattern_pool = require_tag!(doc.root_element(), "PatternPool");
 
-    PatternPool {
-        patterns: collect_patterns(&pattern_pool)
-    }
+    Ok(PatternPool {
+        patterns: collect_patterns(&pattern_pool)?
+    })
 }
 
-fn collect_patterns(pattern_pool: &roxmltree::Node) -> Vec<Pattern> {
-    let patterns = find_tag!(pattern_pool, "Patterns").unwrap();
+fn collect_patterns(pattern_pool: &roxmltree::Node) -> ParseResult<Vec<Pattern>> {
+    let patterns = require_tag!(pattern_pool, "Patterns");
 
-    patterns.children().filter(|n| n.is_element()).map(|n|
-        Pattern {
-            number_of_lines: find_tag_text_parsed!(n, "NumberOfLines").unwrap(),
-            tracks: collect_pattern_tracks(&n)
-        }
-    ).collect()
+    patterns.children().filter(|n| n.is_element()).map(|n| -> ParseResult<Pattern> {
+        Ok(Pattern {
+            name: find_tag_text!(n, "Name", "").to_string(),
+            number_of_lines: find_tag_text_parsed!(n, "NumberOfLines"),
+            tracks: collect_pattern_tracks(&n)?
+        })
+    }).collect()
 }
 
-fn collect_pattern_tracks(pattern: &roxmltree::Node) -> Vec<PatternTrack> {
-    let tracks = find_tag!(pattern, "Tracks").unwrap();
+fn collect_pattern_tracks(pattern: &roxmltree::Node) -> ParseResult<Vec<PatternTrack>> {
+    let tracks = require_tag!(pattern, "Tracks");
 
-    tracks.children().filter(|n| n.is_element()).map(|n|
-        PatternTrack {
-            r#type: n.attribute("type").unwrap().to_string(),
-            alias_pattern_index: find_tag_text_parsed!(n, "AliasPatternIndex").unwrap(),
-            lines: collect_lines(&n)
-        }
-    ).collect()
+    tracks.children().filter(|n| n.is_element()).map(|n| -> ParseResult<PatternTrack> {
+        Ok(PatternTrack {
+            r#type: n.attribute("type").unwrap_or(n.tag_name().name()).to_string(),
+            alias_pattern_index: find_tag_text!(n, "AliasPatternIndex", "-1").trim().parse().map_err(|_| simple_error::SimpleError::new("<AliasPatternIndex> is not valid"))?,
+            lines: collect_lines(&n)?
+        })
+    }).collect()
 }
 
-fn collect_lines(pattern_track: &roxmltree::Node) -> Vec<Line> {
+fn collect_lines(pattern_track: &roxmltree::Node) -> ParseResult<Vec<Line>> {
     match find_tag!(pattern_track, "Lines") {
         Some(lines) => {
-            lines.children().filter(|n| n.is_element()).map(|n|
-                Line {
-                    index: n.attribute("index").unwrap().parse().unwrap(),
+            lines.children().filter(|n| n.is_element()).map(|n| -> ParseResult<Line> {
+                let index = match n.attribute("index").and_then(|index| index.parse().ok()) {
+                    Some(index) => index,
+                    None => { bail!("<Line> has no valid index"); }
+                };
+                Ok(Line {
+                    index,
                     note_columns: collect_note_columns(&n),
                     effect_columns: collect_effect_columns(&n)
-                }
-            ).collect()
+                })
+            }).collect()
         },
-        None => { vec![] }
+        None => { Ok(vec![]) }
     }
 }
 
@@ -224,10 +404,10 @@ fn collect_note_columns(lines: &roxmltree::Node) -> Vec<Option<NoteColumn>> {
             note_columns.children().filter(|n| n.is_element() && n.has_tag_name("NoteColumn")).map(|n|
                 if n.has_children() {
                     Some(NoteColumn {
-                        note: find_tag_text!(n, "Note", "   ").unwrap().to_string(),
-                        instrument: find_tag_text!(n, "Instrument", "..").unwrap().to_string(),
-                        volume: find_tag_text!(n, "Volume", "..").unwrap().to_string(),
-                        panning: find_tag_text!(n, "Panning", "..").unwrap().to_string()
+                        note: find_tag_text!(n, "Note", "   ").to_string(),
+                        instrument: find_tag_text!(n, "Instrument", "..").to_string(),
+                        volume: find_tag_text!(n, "Volume", "..").to_string(),
+                        panning: find_tag_text!(n, "Panning", "..").to_string()
                     })
                 } else {
                     None
@@ -244,8 +424,8 @@ fn collect_effect_columns(lines: &roxmltree::Node) -> Vec<Option<EffectColumn>>
             effect_columns.children().filter(|n| n.is_element() && n.has_tag_name("EffectColumn")).map(|n|
                 if n.has_children() {
                     Some(EffectColumn {
-                        value: find_tag_text!(n, "Value", "00").unwrap().to_string(),
-                        number: find_tag_text!(n, "Number", "  ").unwrap().to_string(),
+                        value: find_tag_text!(n, "Value", "00").to_string(),
+                        number: find_tag_text!(n, "Number", "  ").to_string(),
                     })
                 } else {
                     None
@@ -256,37 +436,161 @@ fn collect_effect_columns(lines: &roxmltree::Node) -> Vec<Option<EffectColumn>>
     }
 }
 
-fn make_pattern_sequence(doc: &roxmltree::Document) -> PatternSequence {
-    let pattern_sequence = find_tag!(doc.root_element(), "PatternSequence").unwrap();
+fn make_pattern_sequence(doc: &roxmltree::Document) -> ParseResult<PatternSequence> {
+    let pattern_sequence = require_tag!(doc.root_element(), "PatternSequence");
 
-    PatternSequence {
-        sequence_entries: collect_sequence_entries(&pattern_sequence)
-    }
+    Ok(PatternSequence {
+        sequence_entries: collect_sequence_entries(&pattern_sequence)?
+    })
 }
 
-fn collect_sequence_entries(pattern_sequence: &roxmltree::Node) -> Vec<SequenceEntry> {
+fn collect_sequence_entries(pattern_sequence: &roxmltree::Node) -> ParseResult<Vec<SequenceEntry>> {
     match find_tag!(pattern_sequence, "SequenceEntries") {
         Some(sequence_entry) => {
-            sequence_entry.children().filter(|n| n.is_element() && n.has_tag_name("SequenceEntry") && n.has_children()).map(|n|
-                SequenceEntry {
-                    pattern: find_tag_text_parsed!(n, "Pattern").unwrap(),
-                    section_name: find_tag_text_parsed!(n, "SectionName").unwrap(),
-                    muted_tracks: collect_muted_tracks(&n)
-                }
-            ).collect()
+            sequence_entry.children().filter(|n| n.is_element() && n.has_tag_name("SequenceEntry") && n.has_children()).map(|n| -> ParseResult<SequenceEntry> {
+                Ok(SequenceEntry {
+                    pattern: find_tag_text_parsed!(n, "Pattern"),
+                    section_name: find_tag_text!(n, "SectionName", "").to_string(),
+                    muted_tracks: collect_muted_tracks(&n)?
+                })
+            }).collect()
         },
-        None => { vec![] }
+        None => { Ok(vec![]) }
     }
 }
 
-fn collect_muted_tracks(sequence_entry: &roxmltree::Node) -> Vec<u32> {
+fn collect_muted_tracks(sequence_entry: &roxmltree::Node) -> ParseResult<Vec<u32>> {
     match find_tag!(sequence_entry, "MutedTracks") {
         Some(muted_tracks) => {
             muted_tracks.children()
                 .filter(|n| n.is_element() && n.has_tag_name("MutedTrack"))
-                .map(|n| n.text().unwrap().parse().unwrap())
+                .map(|n| n.text().unwrap_or("").trim().parse().map_err(|_| simple_error::SimpleError::new("<MutedTrack> is not valid").into()))
                 .collect()
         },
-        None => { vec![] }
+        None => { Ok(vec![]) }
+    }
+}
+
+// Builds small songs for the tests.  The song data starts out at 120 BPM, 4
+// lines per beat and 6 ticks per line, and every track shows a note column and
+// an effect column.
+#[cfg(test)]
+pub struct TestSong {
+    global_song_data: Vec<(&'static str, String)>,
+    tracks: Vec<&'static str>,
+    patterns: Vec<TestPattern>,
+    sequence: Vec<(usize, &'static str)>
+}
+
+#[cfg(test)]
+struct TestPattern {
+    name: &'static str,
+    number_of_lines: u32,
+    effects: Vec<(usize, u32, &'static str, &'static str)> // track, line, command and value
+}
+
+#[cfg(test)]
+impl Default for TestSong {
+    fn default() -> TestSong {
+        TestSong {
+            global_song_data: vec![
+                ("BeatsPerMin", String::from("120")),
+                ("LinesPerBeat", String::from("4")),
+                ("TicksPerLine", String::from("6"))
+            ],
+            tracks: vec![],
+            patterns: vec![],
+            sequence: vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+impl TestSong {
+    // Sets a tag of the global song data, e.g. `SongName`.
+    pub fn global(mut self, tag: &'static str, value: &str) -> TestSong {
+        self.global_song_data.retain(|&(t, _)| t != tag);
+        self.global_song_data.push((tag, value.to_string()));
+        self
+    }
+
+    pub fn track(mut self, name: &'static str) -> TestSong {
+        self.tracks.push(name);
+        self
+    }
+
+    pub fn pattern(mut self, name: &'static str, number_of_lines: u32) -> TestSong {
+        self.patterns.push(TestPattern { name, number_of_lines, effects: vec![] });
+        self
+    }
+
+    // Adds an effect to the last pattern.
+    pub fn effect(mut self, track: usize, line: u32, number: &'static str, value: &'static str) -> TestSong {
+        self.patterns.last_mut().expect("effect before the first pattern").effects.push((track, line, number, value));
+        self
+    }
+
+    pub fn sequence(mut self, pattern: usize, section_name: &'static str) -> TestSong {
+        self.sequence.push((pattern, section_name));
+        self
+    }
+
+    pub fn xml(&self) -> String {
+        let global_song_data: String = self.global_song_data.iter().map(|(tag, value)| format!("<{0}>{1}</{0}>", tag, value)).collect();
+        let tracks: String = self.tracks.iter().map(|name| format!(
+            "<SequencerTrack><Name>{}</Name><NumberOfVisibleNoteColumns>1</NumberOfVisibleNoteColumns><NumberOfVisibleEffectColumns>1</NumberOfVisibleEffectColumns>\
+             <VolumeColumnIsVisible>false</VolumeColumnIsVisible><PanningColumnIsVisible>false</PanningColumnIsVisible><DelayColumnIsVisible>false</DelayColumnIsVisible></SequencerTrack>",
+            name
+        )).collect();
+        let patterns: String = self.patterns.iter().map(|pattern| {
+            let pattern_tracks: String = (0..self.tracks.len()).map(|track| {
+                let mut effects: Vec<(u32, &str, &str)> = pattern.effects.iter()
+                    .filter(|effect| effect.0 == track)
+                    .map(|&(_, line, number, value)| (line, number, value))
+                    .collect();
+                effects.sort_by_key(|effect| effect.0);
+
+                let mut lines = String::new();
+                for (i, &(line, number, value)) in effects.iter().enumerate() {
+                    if i == 0 || effects[i - 1].0 != line {
+                        lines += &format!("<Line index=\"{}\"><EffectColumns>", line);
+                    }
+                    lines += &format!("<EffectColumn><Value>{}</Value><Number>{}</Number></EffectColumn>", value, number);
+                    if effects.get(i + 1).is_none_or(|next| next.0 != line) {
+                        lines += "</EffectColumns></Line>";
+                    }
+                }
+                format!("<PatternTrack><Lines>{}</Lines></PatternTrack>", lines)
+            }).collect();
+            format!("<Pattern><Name>{}</Name><NumberOfLines>{}</NumberOfLines><Tracks>{}</Tracks></Pattern>", pattern.name, pattern.number_of_lines, pattern_tracks)
+        }).collect();
+        let sequence: String = self.sequence.iter().map(|(pattern, section_name)| format!(
+            "<SequenceEntry><Pattern>{}</Pattern><SectionName>{}</SectionName></SequenceEntry>", pattern, section_name
+        )).collect();
+
+        format!(
+            "<RenoiseSong><GlobalSongData>{}</GlobalSongData><Tracks>{}</Tracks><PatternPool><Patterns>{}</Patterns></PatternPool>\
+             <PatternSequence><SequenceEntries>{}</SequenceEntries></PatternSequence></RenoiseSong>",
+            global_song_data, tracks, patterns, sequence
+        )
+    }
+
+    pub fn song(&self) -> Song {
+        Song::from_xml(self.xml()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_tempo_is_an_error() {
+        assert!(Song::from_xml(TestSong::default().xml()).is_ok());
+
+        for tag in ["BeatsPerMin", "LinesPerBeat", "TicksPerLine"].iter() {
+            let error = Song::from_xml(TestSong::default().global(tag, "0").xml()).unwrap_err();
+            assert_eq!(error.to_string(), format!("<{}> of <GlobalSongData> is 0", tag));
+        }
     }
 }
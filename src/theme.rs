@@ -0,0 +1,65 @@
+extern crate image;
+
+pub struct ColorPair {
+    pub normal: image::Rgba<u8>,
+    pub highlighted: image::Rgba<u8>
+}
+
+impl ColorPair {
+    pub fn get(&self, highlighted: bool) -> image::Rgba<u8> {
+        if highlighted { self.highlighted }
+        else { self.normal }
+    }
+}
+
+pub static COLOR_BACK:      ColorPair = ColorPair { normal: image::Rgba([0x15, 0x15, 0x15, 255]), highlighted: image::Rgba([0x29, 0x29, 0x29, 255]) };
+pub static COLOR_DEFAULT:   ColorPair = ColorPair { normal: image::Rgba([0x94, 0x94, 0x94, 255]), highlighted: image::Rgba([0xFF, 0xFF, 0xFF, 255]) };
+pub static COLOR_VOLUME:    ColorPair = ColorPair { normal: image::Rgba([0xD4, 0xCE, 0x2A, 255]), highlighted: image::Rgba([0xBF, 0xAE, 0x25, 255]) };
+pub static COLOR_PANNING:   ColorPair = ColorPair { normal: image::Rgba([0x9D, 0xD6, 0x8C, 255]), highlighted: image::Rgba([0x81, 0xAF, 0x72, 255]) };
+pub static COLOR_PITCH:     ColorPair = ColorPair { normal: image::Rgba([0xB4, 0x4F, 0x21, 255]), highlighted: image::Rgba([0x9B, 0x44, 0x1D, 255]) };
+pub static COLOR_DELAY:     ColorPair = ColorPair { normal: image::Rgba([0x42, 0xC1, 0xEA, 255]), highlighted: image::Rgba([0x3D, 0xB4, 0xDA, 255]) };
+pub static COLOR_GLOBAL_FX: ColorPair = ColorPair { normal: image::Rgba([0xFD, 0x97, 0x14, 255]), highlighted: image::Rgba([0xC6, 0x76, 0x10, 255]) };
+pub static COLOR_OTHER_FX:  ColorPair = ColorPair { normal: image::Rgba([0xBA, 0x68, 0xBB, 255]), highlighted: image::Rgba([0x9A, 0x56, 0x9B, 255]) };
+#[allow(dead_code)]
+pub static COLOR_DSP_FX:    ColorPair = ColorPair { normal: image::Rgba([0xDB, 0xDB, 0xDB, 255]), highlighted: image::Rgba([0xE5, 0xE5, 0xE5, 255]) };
+pub static COLOR_UNUSED_FX: ColorPair = ColorPair { normal: image::Rgba([0x9C, 0x9C, 0x9C, 255]), highlighted: image::Rgba([0x9C, 0x9C, 0x9C, 255]) };
+
+pub static COLOR_ALIAS:      ColorPair = ColorPair { normal: image::Rgba([0x6F, 0x8F, 0xD9, 255]), highlighted: image::Rgba([0x8C, 0xA8, 0xEB, 255]) };
+pub static COLOR_ALIAS_BACK: ColorPair = ColorPair { normal: image::Rgba([0x1B, 0x22, 0x33, 255]), highlighted: image::Rgba([0x25, 0x2F, 0x47, 255]) };
+pub static COLOR_PLAYHEAD:   ColorPair = ColorPair { normal: image::Rgba([0x3B, 0x30, 0x14, 255]), highlighted: image::Rgba([0x55, 0x44, 0x1A, 255]) };
+
+pub static COLOR_DIFF_ADDED:    ColorPair = ColorPair { normal: image::Rgba([0x16, 0x3A, 0x1C, 255]), highlighted: image::Rgba([0x1E, 0x4E, 0x26, 255]) };
+pub static COLOR_DIFF_REMOVED:  ColorPair = ColorPair { normal: image::Rgba([0x45, 0x17, 0x17, 255]), highlighted: image::Rgba([0x5C, 0x1F, 0x1F, 255]) };
+pub static COLOR_DIFF_MODIFIED: ColorPair = ColorPair { normal: image::Rgba([0x42, 0x36, 0x10, 255]), highlighted: image::Rgba([0x57, 0x48, 0x16, 255]) };
+
+static INSTRUMENT_PALETTE: [[u8; 3]; 16] = [
+    [0xE6, 0x55, 0x4E], [0xF2, 0x9B, 0x38], [0xE8, 0xD4, 0x4D], [0x9B, 0xD1, 0x52],
+    [0x4C, 0xB8, 0x6E], [0x3F, 0xC4, 0xBE], [0x4A, 0x9E, 0xE8], [0x6E, 0x6E, 0xF0],
+    [0xA8, 0x6A, 0xE8], [0xDD, 0x6A, 0xC8], [0xF0, 0x8C, 0x9E], [0xC9, 0xA2, 0x7C],
+    [0x9C, 0xC9, 0xA8], [0xA3, 0xBF, 0xE8], [0xD9, 0xB8, 0xF0], [0xC8, 0xC8, 0x96]
+];
+
+pub fn instrument_color(instrument: u32) -> image::Rgba<u8> {
+    let [r, g, b] = INSTRUMENT_PALETTE[instrument as usize % INSTRUMENT_PALETTE.len()];
+    image::Rgba([r, g, b, 255])
+}
+
+pub fn fx_color(number: &str) -> &'static ColorPair {
+    match number.as_bytes() {
+        [b'Z', b'T'] | [b'Z', b'L'] | [b'Z', b'K'] | [b'Z', b'G'] | [b'Z', b'B'] | [b'Z', b'D'] => &COLOR_GLOBAL_FX,
+        [_, b'A'] | [_, b'U'] | [_, b'D'] | [_, b'G'] | [_, b'V'] => &COLOR_PITCH,
+        [_, b'I'] | [_, b'O'] | [_, b'T'] | [_, b'C'] | [_, b'M'] | [_, b'L'] => &COLOR_VOLUME,
+        [_, b'S'] | [_, b'B'] | [_, b'E'] | [_, b'Q'] | [_, b'R'] | [_, b'Y'] => &COLOR_DELAY,
+        [_, b'N'] | [_, b'P'] | [_, b'W'] => &COLOR_PANNING,
+        [_, b'X'] | [_, b'Z'] | [_, b'J'] => &COLOR_OTHER_FX,
+        _ => &COLOR_UNUSED_FX,
+    }
+}
+
+pub fn fx_command(number: &str) -> String {
+    if number.as_bytes()[0] == b'0' {
+        String::from_utf8(vec!(b' ', number.as_bytes()[1])).unwrap()
+    } else {
+        number.to_string()
+    }
+}
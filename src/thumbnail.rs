@@ -0,0 +1,90 @@
+extern crate image;
+
+use super::layout::{ColumnKind, Layout};
+use super::raster;
+use super::theme::*;
+
+// Renders a small overview of a pattern of exactly `width` by `height`
+// pixels, with each cell drawn as a block coloured by what is in it instead
+// of as text.  Dense patterns are scaled down, averaging the cells, so that
+// busy parts still stand out.
+pub fn render(song: &super::renoise::Song, pattern: usize, width: u32, height: u32) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    let pat = &song.pattern_pool.patterns[pattern];
+    let track_indices: Vec<usize> = (0..pat.tracks.len()).collect();
+    let layout = Layout::new(song, &track_indices);
+
+    // one column of cells per column of the layout, with an empty column
+    // between tracks
+    let mut columns: Vec<Option<(usize, ColumnKind, usize)>> = vec![];
+    for track_layout in layout.tracks.iter() {
+        if !columns.is_empty() {
+            columns.push(None);
+        }
+        columns.extend(track_layout.columns.iter().map(|column| Some((track_layout.index, column.kind, column.index))));
+    }
+
+    // draw every cell as a block of whole pixels at least as large as its
+    // share of the thumbnail, and scale the result down to the exact size
+    let cell_width: u32 = width.div_ceil(columns.len().max(1) as u32).max(1);
+    let cell_height: u32 = height.div_ceil(pat.number_of_lines.max(1)).max(1);
+    let grid_width: u32 = cell_width * columns.len().max(1) as u32;
+    let mut imgbuf = image::ImageBuffer::new(grid_width, cell_height * pat.number_of_lines.max(1));
+
+    for line in 0..pat.number_of_lines {
+        let highlighted: bool = line % song.global_song_data.lines_per_beat == 0;
+        raster::fill_rect(&mut imgbuf, 0, line * cell_height, grid_width, cell_height, &COLOR_BACK.get(highlighted));
+    }
+
+    for (position, column) in columns.iter().enumerate() {
+        let (track, kind, index) = match *column {
+            Some(column) => column,
+            None => { continue; }
+        };
+
+        for line in song.pattern_track_lines(pattern, track).iter().filter(|line| line.index < pat.number_of_lines) {
+            if let Some(color) = cell_color(song, track, line, kind, index) {
+                raster::fill_rect(&mut imgbuf, position as u32 * cell_width, line.index * cell_height, cell_width, cell_height, &color);
+            }
+        }
+    }
+
+    image::imageops::resize(&imgbuf, width, height, image::FilterType::Triangle)
+}
+
+// Renders the thumbnails of the patterns in the order of the pattern
+// sequence, one below the other.
+pub fn render_song(song: &super::renoise::Song, width: u32, height: u32) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    let entries = &song.pattern_sequence.sequence_entries;
+    let mut imgbuf = image::ImageBuffer::new(width, height * entries.len() as u32);
+
+    for (index, entry) in entries.iter().enumerate() {
+        let thumbnail = render(song, entry.pattern as usize, width, height);
+        image::imageops::replace(&mut imgbuf, &thumbnail, 0, index as u32 * height);
+    }
+
+    imgbuf
+}
+
+// Returns the colour of a cell: the track colour for notes, the colours of
+// the pattern editor for volume, panning and effects, or None if the cell is
+// empty.
+fn cell_color(song: &super::renoise::Song, track: usize, line: &super::renoise::Line, kind: ColumnKind, index: usize) -> Option<image::Rgba<u8>> {
+    let note_column = line.note_columns.get(index).and_then(|n| n.as_ref());
+    let effect_column = line.effect_columns.get(index).and_then(|e| e.as_ref());
+
+    match kind {
+        ColumnKind::Note => note_column.and_then(|note| {
+            if note.pitch().is_some() {
+                let [r, g, b] = song.tracks[track].rgb();
+                Some(image::Rgba([r, g, b, 255]))
+            } else if note.is_note_off() {
+                Some(COLOR_DEFAULT.normal)
+            } else {
+                None
+            }
+        }),
+        ColumnKind::Volume => note_column.filter(|n| n.volume != "..").map(|_| COLOR_VOLUME.normal),
+        ColumnKind::Panning => note_column.filter(|n| n.panning != "..").map(|_| COLOR_PANNING.normal),
+        ColumnKind::Effect => effect_column.filter(|e| e.number.trim() != "").map(|e| fx_color(&e.number).normal)
+    }
+}
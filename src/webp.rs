@@ -0,0 +1,299 @@
+extern crate image;
+
+// A lossless WebP (VP8L) encoder.  It only uses what works well for rendered
+// patterns: runs of the same pixel are copied from the pixel to the left, and
+// everything else is stored as literals with a prefix code per channel.
+
+use std::io::Write;
+
+const MAX_SIZE:           u32 = 1 << 14;
+const MAX_LENGTH:         usize = 4096;
+const MAX_CODE_LENGTH:    u32 = 15;
+const MAX_LENGTH_CODE_LENGTH: u32 = 7;
+const NUM_LENGTH_CODES:   usize = 24;
+const NUM_DISTANCE_CODES: usize = 40;
+const LEFT_PIXEL:         usize = 1; // prefix code of the distance code for the pixel to the left
+
+// The order in which the code lengths of the code length code are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+enum Token {
+    Literal([u8; 4]), // RGBA
+    Copy(usize)       // of the pixel to the left, this many times
+}
+
+pub fn encode<W: Write>(mut out: W, image: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> Result<(), Box<dyn std::error::Error>> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        bail!("WebP images must be between 1 and {} pixels wide and high, not {}x{}", MAX_SIZE, width, height);
+    }
+
+    let tokens = tokenize(image);
+
+    let mut histograms: [Vec<u32>; 5] = [
+        vec![0; 256 + NUM_LENGTH_CODES],
+        vec![0; 256],
+        vec![0; 256],
+        vec![0; 256],
+        vec![0; NUM_DISTANCE_CODES]
+    ];
+    for token in tokens.iter() {
+        match *token {
+            Token::Literal([r, g, b, a]) => {
+                histograms[0][g as usize] += 1;
+                histograms[1][r as usize] += 1;
+                histograms[2][b as usize] += 1;
+                histograms[3][a as usize] += 1;
+            },
+            Token::Copy(length) => {
+                histograms[0][256 + prefix_code(length).0] += 1;
+                histograms[4][LEFT_PIXEL] += 1;
+            }
+        }
+    }
+
+    let mut bits = BitWriter::new();
+    bits.write(0x2f, 8);
+    bits.write(width - 1, 14);
+    bits.write(height - 1, 14);
+    bits.write(image.pixels().any(|pixel| pixel[3] != 255) as u32, 1);
+    bits.write(0, 3); // version
+    bits.write(0, 1); // no transforms
+    bits.write(0, 1); // no colour cache
+    bits.write(0, 1); // a single set of prefix codes for the whole image
+
+    let codes: Vec<PrefixCode> = histograms.iter().map(|histogram| PrefixCode::write(&mut bits, histogram)).collect();
+
+    for token in tokens.iter() {
+        match *token {
+            Token::Literal([r, g, b, a]) => {
+                codes[0].write_symbol(&mut bits, g as usize);
+                codes[1].write_symbol(&mut bits, r as usize);
+                codes[2].write_symbol(&mut bits, b as usize);
+                codes[3].write_symbol(&mut bits, a as usize);
+            },
+            Token::Copy(length) => {
+                let (prefix, extra_bits, extra) = prefix_code(length);
+                codes[0].write_symbol(&mut bits, 256 + prefix);
+                bits.write(extra, extra_bits);
+                codes[4].write_symbol(&mut bits, LEFT_PIXEL);
+            }
+        }
+    }
+
+    let data = bits.finish();
+    let padding = data.len() % 2;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(4 + 8 + data.len() as u32 + padding as u32).to_le_bytes())?;
+    out.write_all(b"WEBPVP8L")?;
+    out.write_all(&(data.len() as u32).to_le_bytes())?;
+    out.write_all(&data)?;
+    out.write_all(&[0; 1][..padding])?;
+
+    Ok(())
+}
+
+// Splits the image into literal pixels and runs of the pixel before them.
+fn tokenize(image: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> Vec<Token> {
+    let pixels: Vec<[u8; 4]> = image.pixels().map(|pixel| pixel.0).collect();
+    let mut tokens: Vec<Token> = vec![];
+
+    let mut position = 0;
+    while position < pixels.len() {
+        if position > 0 {
+            let previous = pixels[position - 1];
+            let run = pixels[position..].iter().take(MAX_LENGTH).take_while(|&&pixel| pixel == previous).count();
+            if run >= 2 {
+                tokens.push(Token::Copy(run));
+                position += run;
+                continue;
+            }
+        }
+
+        tokens.push(Token::Literal(pixels[position]));
+        position += 1;
+    }
+
+    tokens
+}
+
+// Splits a length or distance code into its prefix code, and the number and
+// value of the extra bits that follow it.
+fn prefix_code(value: usize) -> (usize, u32, u32) {
+    let value = value - 1;
+    if value < 4 {
+        return (value, 0, 0);
+    }
+
+    let highest_bit = usize::BITS - 1 - value.leading_zeros();
+    let second_bit = (value >> (highest_bit - 1)) & 1;
+    let extra_bits = highest_bit - 1;
+
+    (2 * highest_bit as usize + second_bit, extra_bits, (value & ((1 << extra_bits) - 1)) as u32)
+}
+
+// A canonical prefix code, with the codes stored bit reversed because the
+// bits are read least significant first.
+struct PrefixCode {
+    codes: Vec<(u32, u32)> // code and its length, by symbol
+}
+
+impl PrefixCode {
+    // Builds a prefix code for the symbols counted in `histogram` and writes
+    // its description.
+    fn write(bits: &mut BitWriter, histogram: &[u32]) -> PrefixCode {
+        let used: Vec<usize> = (0..histogram.len()).filter(|&symbol| histogram[symbol] > 0).collect();
+
+        // a single symbol (or none at all) takes no bits; a simple code
+        // describes it without listing the length of every symbol
+        if used.len() <= 1 && used.first().is_none_or(|&symbol| symbol < 256) {
+            let symbol = used.first().cloned().unwrap_or(0);
+            bits.write(1, 1); // simple code
+            bits.write(0, 1); // of one symbol
+            if symbol < 2 {
+                bits.write(0, 1);
+                bits.write(symbol as u32, 1);
+            } else {
+                bits.write(1, 1);
+                bits.write(symbol as u32, 8);
+            }
+            return PrefixCode { codes: vec![(0, 0); histogram.len()] };
+        }
+
+        let lengths = code_lengths(histogram, MAX_CODE_LENGTH);
+
+        // the code lengths are themselves stored with a prefix code
+        let mut length_histogram: Vec<u32> = vec![0; CODE_LENGTH_ORDER.len()];
+        for &length in lengths.iter() {
+            length_histogram[length as usize] += 1;
+        }
+        let length_code_lengths = code_lengths(&length_histogram, MAX_LENGTH_CODE_LENGTH);
+        let length_code = PrefixCode::from_lengths(&length_code_lengths);
+
+        let count = CODE_LENGTH_ORDER.iter().rposition(|&index| length_code_lengths[index] > 0).map_or(0, |last| last + 1).max(4);
+        bits.write(0, 1); // normal code
+        bits.write(count as u32 - 4, 4);
+        for &index in CODE_LENGTH_ORDER[..count].iter() {
+            bits.write(length_code_lengths[index], 3);
+        }
+        bits.write(0, 1); // lengths of all symbols follow
+        for &length in lengths.iter() {
+            length_code.write_symbol(bits, length as usize);
+        }
+
+        PrefixCode::from_lengths(&lengths)
+    }
+
+    // Assigns canonical codes to the symbols.  A code with a single symbol
+    // takes no bits, as decoders read it without looking at the bits.
+    fn from_lengths(lengths: &[u32]) -> PrefixCode {
+        if lengths.iter().filter(|&&length| length > 0).count() <= 1 {
+            return PrefixCode { codes: vec![(0, 0); lengths.len()] };
+        }
+
+        let mut next_code: Vec<u32> = vec![0; MAX_CODE_LENGTH as usize + 2];
+        for length in 1..=MAX_CODE_LENGTH as usize {
+            let count = lengths.iter().filter(|&&l| l as usize == length).count() as u32;
+            next_code[length + 1] = (next_code[length] + count) << 1;
+        }
+
+        let codes = lengths.iter().map(|&length| {
+            if length == 0 {
+                return (0, 0);
+            }
+            let code = next_code[length as usize];
+            next_code[length as usize] += 1;
+            (code.reverse_bits() >> (32 - length), length)
+        }).collect();
+
+        PrefixCode { codes }
+    }
+
+    fn write_symbol(&self, bits: &mut BitWriter, symbol: usize) {
+        let (code, length) = self.codes[symbol];
+        bits.write(code, length);
+    }
+}
+
+// Returns the code lengths of a Huffman code for the symbols counted in
+// `histogram`, at most `max_length` bits long.  If the code gets too long,
+// rare symbols are counted as more frequent than they are until it fits.
+fn code_lengths(histogram: &[u32], max_length: u32) -> Vec<u32> {
+    let mut minimum_count: u32 = 1;
+
+    loop {
+        // nodes of the Huffman tree, the leaves first
+        let mut weights: Vec<u64> = vec![];
+        let mut parents: Vec<usize> = vec![];
+        let mut leaves: Vec<(usize, usize)> = vec![]; // symbol and node
+        for (symbol, &count) in histogram.iter().enumerate() {
+            if count > 0 {
+                leaves.push((symbol, weights.len()));
+                weights.push(count.max(minimum_count) as u64);
+                parents.push(usize::MAX);
+            }
+        }
+
+        let mut lengths: Vec<u32> = vec![0; histogram.len()];
+        if leaves.len() == 1 {
+            lengths[leaves[0].0] = 1;
+            return lengths;
+        }
+
+        let mut queue: std::collections::BinaryHeap<std::cmp::Reverse<(u64, usize)>> = (0..weights.len()).map(|node| std::cmp::Reverse((weights[node], node))).collect();
+        while queue.len() > 1 {
+            let std::cmp::Reverse((first_weight, first)) = queue.pop().unwrap();
+            let std::cmp::Reverse((second_weight, second)) = queue.pop().unwrap();
+            let node = weights.len();
+            weights.push(first_weight + second_weight);
+            parents.push(usize::MAX);
+            parents[first] = node;
+            parents[second] = node;
+            queue.push(std::cmp::Reverse((first_weight + second_weight, node)));
+        }
+
+        for &(symbol, leaf) in leaves.iter() {
+            let mut node = leaf;
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                lengths[symbol] += 1;
+            }
+        }
+
+        if lengths.iter().all(|&length| length <= max_length) {
+            return lengths;
+        }
+        minimum_count *= 2;
+    }
+}
+
+// Writes bits least significant first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u64,
+    count: u32
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { bytes: vec![], bits: 0, count: 0 }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}